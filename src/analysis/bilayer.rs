@@ -0,0 +1,203 @@
+//! Structure of a bilayer: which leaflet each lipid is in, where the midplane is, how thick it is, and how much of it each
+//! lipid takes up.
+
+use super::Shape;
+use super::clusters::{self, ClusterKind};
+use crate::observables::Observable;
+use crate::types::*;
+
+/// The midplane is cut into bins this long to get the local thickness along it.
+const THICKNESS_BIN_LENGTH: f32 = 20.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Leaflet {
+    /// Heads on the side the normal points to
+    Upper,
+    Lower,
+}
+
+impl Leaflet {
+    pub fn name(self) -> &'static str {
+        match self {
+            Leaflet::Upper => "upper",
+            Leaflet::Lower => "lower",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bilayer {
+    /// Indices into `State::lipids`, and which leaflet each is in
+    pub leaflets: Vec<(usize, Leaflet)>,
+    /// A point on the (straight line) midplane
+    pub midplane_point: Point,
+    /// Unit normal to the midplane, pointing at the upper leaflet
+    pub normal: Vector,
+    /// Mean head-head distance across the leaflets
+    pub thickness: f32,
+    /// Thickness in each stretch of `THICKNESS_BIN_LENGTH` along the midplane that has heads from both leaflets
+    pub local_thickness: Vec<f32>,
+    /// Length of midplane per lipid in a leaflet, averaged over both leaflets (the 2D analogue of area per lipid)
+    pub length_per_lipid: f32,
+}
+
+impl Bilayer {
+    pub fn count(&self, leaflet: Leaflet) -> usize {
+        self.leaflets.iter().filter(|(_, l)| *l == leaflet).count()
+    }
+}
+
+/// The biggest bilayer patch in the state, if there is one.
+pub fn largest(state: &State) -> Option<Bilayer> {
+    largest_following(state, None)
+}
+
+/// Like `largest`, with the normal pointing the same way as `previous` (the normal found in the previous state) so that
+/// the leaflets keep their names when the bilayer stands close to the reference axis.
+pub fn largest_following(state: &State, previous: Option<Vector>) -> Option<Bilayer> {
+    let cluster = clusters::analyze(state).into_iter().find(|c| c.kind == ClusterKind::BilayerPatch)?;
+    analyze(state, &cluster.lipids, previous)
+}
+
+/// Treats `lipids` as one bilayer, with the normal oriented against `previous` if given. `None` if either leaflet ends
+/// up empty.
+pub fn analyze(state: &State, lipids: &[usize], previous: Option<Vector>) -> Option<Bilayer> {
+    let midplane = Shape::of(lipids.iter().map(|i| state.lipids[*i].tail_position));
+    let normal = oriented(midplane.minor_axis(), previous);
+    let leaflets = assign_leaflets(state, lipids, normal);
+    let along = midplane.major_axis;
+
+    // signed distances of heads from the midplane, and where along it they are
+    let heads = |leaflet: Leaflet| -> Vec<(f32, f32)> {
+        leaflets
+            .iter()
+            .filter(|(_, l)| *l == leaflet)
+            .map(|(i, _)| {
+                let offset = state.lipids[*i].head_position - midplane.centroid;
+                (offset.dot(along), offset.dot(normal))
+            })
+            .collect()
+    };
+    let upper = heads(Leaflet::Upper);
+    let lower = heads(Leaflet::Lower);
+    if upper.is_empty() || lower.is_empty() {
+        return None;
+    }
+
+    let mean_height = |heads: &[(f32, f32)]| heads.iter().map(|(_, h)| h).sum::<f32>() / heads.len() as f32;
+    let thickness = mean_height(&upper) - mean_height(&lower);
+
+    let start = upper.iter().chain(lower.iter()).map(|(s, _)| *s).fold(f32::INFINITY, f32::min);
+    let end = upper.iter().chain(lower.iter()).map(|(s, _)| *s).fold(f32::NEG_INFINITY, f32::max);
+    let num_bins = ((end - start) / THICKNESS_BIN_LENGTH).ceil().max(1.0) as usize;
+    let bin_heights = |heads: &[(f32, f32)]| {
+        let mut sums = vec![(0.0, 0); num_bins];
+        for (s, h) in heads {
+            let bin = (((s - start) / THICKNESS_BIN_LENGTH) as usize).min(num_bins - 1);
+            sums[bin].0 += h;
+            sums[bin].1 += 1;
+        }
+        sums
+    };
+    let local_thickness = bin_heights(&upper)
+        .into_iter()
+        .zip(bin_heights(&lower))
+        .filter(|((_, nu), (_, nl))| *nu > 0 && *nl > 0)
+        .map(|((su, nu), (sl, nl))| su / nu as f32 - sl / nl as f32)
+        .collect();
+
+    let extent = |heads: &[(f32, f32)]| {
+        let min = heads.iter().map(|(s, _)| *s).fold(f32::INFINITY, f32::min);
+        let max = heads.iter().map(|(s, _)| *s).fold(f32::NEG_INFINITY, f32::max);
+        max - min
+    };
+    let length_per_lipid = (extent(&upper) / upper.len() as f32 + extent(&lower) / lower.len() as f32) / 2.0;
+
+    Some(Bilayer {
+        leaflets,
+        midplane_point: midplane.centroid,
+        normal,
+        thickness,
+        local_thickness,
+        length_per_lipid,
+    })
+}
+
+/// Each lipid's tail->head direction is compared to the local membrane normal: the minor axis of the lipids around it,
+/// made to agree with the normal of the whole bilayer, itself made to point the same way as `reference`.
+pub fn assign_leaflets(state: &State, lipids: &[usize], reference: Vector) -> Vec<(usize, Leaflet)> {
+    let global_normal = oriented(
+        Shape::of(lipids.iter().map(|i| state.lipids[*i].midpoint())).minor_axis(),
+        Some(reference),
+    );
+    lipids
+        .iter()
+        .map(|i| {
+            let l = &state.lipids[*i];
+            let radius2 = (2.0 * l.tail_length).powi(2);
+            let neighbours = lipids
+                .iter()
+                .map(|j| state.lipids[*j].midpoint())
+                .filter(|p| p.distance2(l.midpoint()) < radius2);
+            let local = Shape::of(neighbours.clone());
+            let mut normal = if neighbours.count() >= 3 && local.anisotropy() > 0.0 {
+                local.minor_axis()
+            } else {
+                global_normal
+            };
+            if normal.dot(global_normal) < 0.0 {
+                normal = -normal;
+            }
+            let leaflet = if -l.direction().dot(normal) > 0.0 {
+                Leaflet::Upper
+            } else {
+                Leaflet::Lower
+            };
+            (*i, leaflet)
+        })
+        .collect()
+}
+
+/// Normals come out of `Shape` pointing either way; this picks the one on the same side as `reference`, or with no
+/// reference (the first frame) the one pointing down the screen (+y), so that the leaflets keep their names from one
+/// frame to the next. Going by the screen alone would swap them whenever a near-vertical bilayer tilts past it.
+fn oriented(normal: Vector, reference: Option<Vector>) -> Vector {
+    let flip = match reference {
+        Some(reference) => normal.dot(reference) < 0.0,
+        None => normal.y < 0.0 || (normal.y == 0.0 && normal.x < 0.0),
+    };
+    if flip { -normal } else { normal }
+}
+
+/// Thickness (mean and spread along the membrane), length per lipid, and leaflet sizes of the largest bilayer patch
+#[derive(Default)]
+pub struct BilayerMetrics {
+    /// Of the previous state, to keep the leaflets' names
+    normal: Option<Vector>,
+}
+
+impl Observable for BilayerMetrics {
+    fn columns(&self) -> Vec<String> {
+        ["thickness", "thickness_std", "length_per_lipid", "upper_leaflet", "lower_leaflet"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        let Some(bilayer) = largest_following(state, self.normal) else {
+            return vec![f64::NAN; 5];
+        };
+        self.normal = Some(bilayer.normal);
+        let n = bilayer.local_thickness.len() as f32;
+        let mean = bilayer.local_thickness.iter().sum::<f32>() / n;
+        let std = (bilayer.local_thickness.iter().map(|t| (t - mean).powi(2)).sum::<f32>() / n).sqrt();
+        vec![
+            bilayer.thickness as f64,
+            std as f64,
+            bilayer.length_per_lipid as f64,
+            bilayer.count(Leaflet::Upper) as f64,
+            bilayer.count(Leaflet::Lower) as f64,
+        ]
+    }
+}
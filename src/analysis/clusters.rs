@@ -0,0 +1,178 @@
+//! Aggregates: lipids whose tails are within interaction range of each other, transitively.
+
+use super::Shape;
+use crate::engine::{MAX_DIST2, TAIL_POINTS};
+use crate::observables::Observable;
+use crate::types::*;
+
+/// Clusters at least this anisotropic are long enough to be bilayer patches, below it they're round enough to be
+/// micelles or vesicles.
+const ELONGATED_ANISOTROPY: f32 = 0.6;
+/// Lipids whose head direction is within ~60° of some reference direction count as pointing along it.
+const ALIGNED: f32 = 0.5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ClusterKind {
+    Monomer,
+    /// Round, with the heads pointing out and the tails in
+    Micelle,
+    /// Elongated, with the lipids lined up across it, heads on both sides
+    BilayerPatch,
+    /// A closed bilayer: round, hollow, heads pointing both out (outer leaflet) and in (inner leaflet)
+    Vesicle,
+    /// None of the above, e.g. something still forming
+    Other,
+}
+
+impl ClusterKind {
+    pub const ALL: [ClusterKind; 5] = [
+        ClusterKind::Monomer,
+        ClusterKind::Micelle,
+        ClusterKind::BilayerPatch,
+        ClusterKind::Vesicle,
+        ClusterKind::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClusterKind::Monomer => "monomer",
+            ClusterKind::Micelle => "micelle",
+            ClusterKind::BilayerPatch => "bilayer_patch",
+            ClusterKind::Vesicle => "vesicle",
+            ClusterKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    /// Indices into `State::lipids`
+    pub lipids: Vec<usize>,
+    pub kind: ClusterKind,
+    /// Of the lipid midpoints
+    pub shape: Shape,
+}
+
+/// Clusters by tail-tail proximity, with the engine's interaction range as the cutoff, largest first.
+pub fn analyze(state: &State) -> Vec<Cluster> {
+    let mut clusters: Vec<_> = find_clusters(state, MAX_DIST2)
+        .into_iter()
+        .map(|lipids| {
+            let shape = Shape::of(lipids.iter().map(|i| state.lipids[*i].midpoint()));
+            let kind = classify(state, &lipids, &shape);
+            Cluster { lipids, kind, shape }
+        })
+        .collect();
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.lipids.len()));
+    clusters
+}
+
+/// Groups of lipid indices, where two lipids are in the same group if any of their tail points are closer than
+/// `sqrt(cutoff2)`, or they're both connected to a third lipid that way.
+pub fn find_clusters(state: &State, cutoff2: f32) -> Vec<Vec<usize>> {
+    let tail_points: Vec<Vec<Point>> = state
+        .lipids
+        .iter()
+        .map(|l| TAIL_POINTS.iter().map(|frac| l.tail_point(*frac)).collect())
+        .collect();
+
+    // union-find, always pointing at the lower index
+    let mut parent: Vec<usize> = (0..state.lipids.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..tail_points.len() {
+        for j in i + 1..tail_points.len() {
+            let close = tail_points[i]
+                .iter()
+                .any(|pi| tail_points[j].iter().any(|pj| pi.distance2(*pj) < cutoff2));
+            if close {
+                let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+                parent[ri.max(rj)] = ri.min(rj);
+            }
+        }
+    }
+
+    let mut clusters: Vec<Vec<usize>> = vec![];
+    let mut cluster_of_root = vec![usize::MAX; parent.len()];
+    for i in 0..parent.len() {
+        let r = root(&mut parent, i);
+        if cluster_of_root[r] == usize::MAX {
+            cluster_of_root[r] = clusters.len();
+            clusters.push(vec![]);
+        }
+        clusters[cluster_of_root[r]].push(i);
+    }
+    clusters
+}
+
+/// Decides from the cluster's shape and how its lipids point relative to it.
+pub fn classify(state: &State, lipids: &[usize], shape: &Shape) -> ClusterKind {
+    if lipids.len() == 1 {
+        return ClusterKind::Monomer;
+    }
+    let n = lipids.len() as f32;
+    let members = || lipids.iter().map(|i| &state.lipids[*i]);
+
+    if shape.anisotropy() >= ELONGATED_ANISOTROPY {
+        // in a bilayer, lipids point along the normal (the minor axis), either way
+        let normal = shape.minor_axis();
+        let across = members().filter(|l| l.direction().dot(normal).abs() > ALIGNED).count() as f32;
+        return if across / n > 0.5 {
+            ClusterKind::BilayerPatch
+        } else {
+            ClusterKind::Other
+        };
+    }
+
+    // heads pointing away from the centre have their head->tail direction pointing at it
+    let radial = |l: &Lipid| {
+        let outward = l.midpoint() - shape.centroid;
+        if outward.magnitude2() > 0.0 {
+            -l.direction().dot(outward.normalize())
+        } else {
+            0.0
+        }
+    };
+    let heads_out = members().filter(|l| radial(l) > ALIGNED).count() as f32 / n;
+    let heads_in = members().filter(|l| radial(l) < -ALIGNED).count() as f32 / n;
+    let mean_tail_length = members().map(|l| l.tail_length).sum::<f32>() / n;
+    let hollow = members().all(|l| l.midpoint().distance(shape.centroid) > mean_tail_length);
+
+    if hollow && heads_out > 0.25 && heads_in > 0.25 {
+        ClusterKind::Vesicle
+    } else if heads_out > 0.6 {
+        ClusterKind::Micelle
+    } else {
+        ClusterKind::Other
+    }
+}
+
+/// Cluster count, size statistics, and how many clusters there are of each kind
+pub struct Clusters;
+
+impl Observable for Clusters {
+    fn columns(&self) -> Vec<String> {
+        let mut columns = vec!["cluster_count".to_string(), "largest_cluster".into(), "mean_cluster_size".into()];
+        columns.extend(ClusterKind::ALL.iter().map(|kind| format!("{}_count", kind.name())));
+        columns
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        let clusters = analyze(state);
+        let count = clusters.len() as f64;
+        let largest = clusters.first().map_or(0, |c| c.lipids.len()) as f64;
+        let mut values = vec![count, largest, state.lipids.len() as f64 / count];
+        values.extend(
+            ClusterKind::ALL
+                .iter()
+                .map(|kind| clusters.iter().filter(|c| c.kind == *kind).count() as f64),
+        );
+        values
+    }
+}
@@ -0,0 +1,129 @@
+//! Lipid mobility over a trajectory: mean squared displacement of the midpoints, and the autocorrelation of the head->tail
+//! direction, with the lateral and rotational diffusion coefficients fitted from them.
+//!
+//! The bounds are walls, not periodic, so positions never need unwrapping. Angles do: they're unwrapped from frame to
+//! frame, which assumes no lipid turns by more than half a turn between saved frames.
+
+use super::fit_line;
+use crate::types::*;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::io::{self, Write};
+
+/// Below this the autocorrelation is mostly noise, so it's left out of the rotational fit.
+const MIN_FIT_CORRELATION: f64 = 0.1;
+
+struct Frame {
+    time: f64,
+    /// Midpoint and unwrapped angle of the head->tail direction, by lipid id
+    lipids: HashMap<LipidId, (Point, f32)>,
+}
+
+/// Averages over every lipid and every pair of frames `lag` frames apart
+#[derive(Debug, Copy, Clone)]
+pub struct Correlation {
+    pub lag: usize,
+    /// Mean time between the frames
+    pub lag_time: f64,
+    pub msd: f64,
+    /// Mean squared change of the unwrapped angle
+    pub angle_msd: f64,
+    /// `<cos(θ(t + lag) - θ(t))>`, i.e. `<d(t)·d(t + lag)>`
+    pub rotational_autocorrelation: f64,
+}
+
+/// Collects frames in order, following lipids by id; a pair of frames only counts the lipids that are in both.
+#[derive(Default)]
+pub struct Diffusion {
+    frames: Vec<Frame>,
+}
+
+impl Diffusion {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_frame(&mut self, state: &State) {
+        let prev = self.frames.last();
+        let lipids = state
+            .lipids
+            .iter()
+            .map(|l| {
+                let d = l.direction();
+                let mut angle = d.y.atan2(d.x);
+                if let Some((_, prev)) = prev.and_then(|p| p.lipids.get(&l.id)) {
+                    angle = prev + (angle - prev + PI).rem_euclid(2.0 * PI) - PI;
+                }
+                (l.id, (l.midpoint(), angle))
+            })
+            .collect();
+        self.frames.push(Frame { time: state.time, lipids });
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// For lags of 1 up to `max_lag` frames (or one less than the number of frames). Assumes the frames were saved at
+    /// even intervals, as the trajectory writers do.
+    pub fn correlations(&self, max_lag: usize) -> Vec<Correlation> {
+        let max_lag = max_lag.min(self.frames.len().saturating_sub(1));
+        (1..=max_lag)
+            .map(|lag| {
+                let (mut time, mut msd, mut angle_msd, mut autocorrelation, mut samples) = (0.0, 0.0, 0.0, 0.0, 0);
+                for (a, b) in self.frames.iter().zip(self.frames[lag..].iter()) {
+                    time += b.time - a.time;
+                    for (id, (pa, angle_a)) in a.lipids.iter() {
+                        let Some((pb, angle_b)) = b.lipids.get(id) else {
+                            continue;
+                        };
+                        let turn = (angle_b - angle_a) as f64;
+                        msd += pa.distance2(*pb) as f64;
+                        angle_msd += turn * turn;
+                        autocorrelation += turn.cos();
+                        samples += 1;
+                    }
+                }
+                let origins = (self.frames.len() - lag) as f64;
+                let samples = samples.max(1) as f64;
+                Correlation {
+                    lag,
+                    lag_time: time / origins,
+                    msd: msd / samples,
+                    angle_msd: angle_msd / samples,
+                    rotational_autocorrelation: autocorrelation / samples,
+                }
+            })
+            .collect()
+    }
+
+    pub fn write_csv(&self, w: &mut impl Write, max_lag: usize) -> io::Result<()> {
+        writeln!(w, "lag,lag_time,msd,angle_msd,rotational_autocorrelation")?;
+        for c in self.correlations(max_lag) {
+            writeln!(
+                w,
+                "{},{},{},{},{}",
+                c.lag, c.lag_time, c.msd, c.angle_msd, c.rotational_autocorrelation
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `D` from `MSD = 4 D t` (in 2D), fitted by least squares with an intercept, which soaks up the ballistic part at short
+/// lags.
+pub fn lateral_coefficient(correlations: &[Correlation]) -> Option<f64> {
+    let (slope, _) = fit_line(correlations.iter().map(|c| (c.lag_time, c.msd)))?;
+    Some(slope / 4.0)
+}
+
+/// `D_r` from `<cos Δθ> = exp(-D_r t)`, fitted to the log of the autocorrelation where it's still well above noise.
+pub fn rotational_coefficient(correlations: &[Correlation]) -> Option<f64> {
+    let (slope, _) = fit_line(
+        correlations
+            .iter()
+            .filter(|c| c.rotational_autocorrelation > MIN_FIT_CORRELATION)
+            .map(|c| (c.lag_time, c.rotational_autocorrelation.ln())),
+    )?;
+    Some(-slope)
+}
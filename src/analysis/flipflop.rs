@@ -0,0 +1,193 @@
+//! Flip-flops: lipids moving from one leaflet of a bilayer to the other.
+//!
+//! Leaflets are assigned in each state by `bilayer::assign_leaflets` on the largest bilayer patch, with its normal kept
+//! pointing the same way as in the previous state so that the leaflets don't swap names. Lipids near an edge or a defect
+//! can be assigned back and forth from one state to the next without really moving, so a lipid only counts as having
+//! changed leaflet once it's been seen in the new one for a few states in a row. Lipids are followed by id.
+
+use super::bilayer::{self, Leaflet};
+use crate::observables::Observable;
+use crate::types::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// States in a row a lipid has to be seen in the other leaflet for it to count as a flip-flop
+pub const DEFAULT_CONFIRM_STATES: usize = 3;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Event {
+    /// Of the first state the lipid was seen in its new leaflet
+    pub tick: u64,
+    pub time: f64,
+    pub lipid: LipidId,
+    pub from: Leaflet,
+    pub to: Leaflet,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Membership {
+    leaflet: Leaflet,
+    /// The other leaflet has been seen this many times in a row since...
+    candidate_count: usize,
+    /// ...this tick & time
+    candidate_since: (u64, f64),
+}
+
+/// Follows leaflet membership through states given in order.
+pub struct FlipFlops {
+    confirm_states: usize,
+    membership: HashMap<LipidId, Membership>,
+    pub events: Vec<Event>,
+    /// Sum over the observed states of (time since the previous one) × (lipids in the bilayer), for the rate
+    lipid_time: f64,
+    last_time: Option<f64>,
+    /// Bilayer normal in the last state that had a bilayer
+    normal: Option<Vector>,
+}
+
+impl Default for FlipFlops {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONFIRM_STATES)
+    }
+}
+
+impl FlipFlops {
+    pub fn new(confirm_states: usize) -> Self {
+        Self {
+            confirm_states: confirm_states.max(1),
+            membership: HashMap::new(),
+            events: vec![],
+            lipid_time: 0.0,
+            last_time: None,
+            normal: None,
+        }
+    }
+
+    /// The events this state completed. Lipids that aren't in the bilayer keep whatever leaflet they were last in.
+    pub fn update(&mut self, state: &State) -> &[Event] {
+        let first_new = self.events.len();
+        let bilayer = bilayer::largest_following(state, self.normal);
+        if let Some(bilayer) = &bilayer {
+            self.normal = Some(bilayer.normal);
+        }
+        let leaflets = bilayer.map_or(vec![], |b| b.leaflets);
+        if let Some(last_time) = self.last_time {
+            self.lipid_time += (state.time - last_time) * leaflets.len() as f64;
+        }
+        self.last_time = Some(state.time);
+
+        for (index, leaflet) in leaflets {
+            let lipid = state.lipids[index].id;
+            let membership = self.membership.entry(lipid).or_insert(Membership {
+                leaflet,
+                candidate_count: 0,
+                candidate_since: (state.tick, state.time),
+            });
+            if membership.leaflet == leaflet {
+                membership.candidate_count = 0;
+                continue;
+            }
+            if membership.candidate_count == 0 {
+                membership.candidate_since = (state.tick, state.time);
+            }
+            membership.candidate_count += 1;
+            if membership.candidate_count >= self.confirm_states {
+                let (tick, time) = membership.candidate_since;
+                self.events.push(Event {
+                    tick,
+                    time,
+                    lipid,
+                    from: membership.leaflet,
+                    to: leaflet,
+                });
+                membership.leaflet = leaflet;
+                membership.candidate_count = 0;
+            }
+        }
+        &self.events[first_new..]
+    }
+
+    /// Flip-flops per lipid in the bilayer per unit time, over everything seen so far
+    pub fn rate(&self) -> Option<f64> {
+        (self.lipid_time > 0.0).then(|| self.events.len() as f64 / self.lipid_time)
+    }
+
+    pub fn count(&self, from: Leaflet) -> usize {
+        self.events.iter().filter(|e| e.from == from).count()
+    }
+
+    pub fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "tick,time,lipid,from,to")?;
+        for e in self.events.iter() {
+            writeln!(w, "{},{},{},{},{}", e.tick, e.time, e.lipid, e.from.name(), e.to.name())?;
+        }
+        Ok(())
+    }
+}
+
+/// Running total of flip-flops (both ways, and just upper->lower), and the rate so far. Only as good as the logging
+/// interval is short compared to how long a flip-flop takes.
+impl Observable for FlipFlops {
+    fn columns(&self) -> Vec<String> {
+        ["flip_flops", "flip_flops_from_upper", "flip_flop_rate"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        self.update(state);
+        vec![
+            self.events.len() as f64,
+            self.count(Leaflet::Upper) as f64,
+            self.rate().unwrap_or(f64::NAN),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bilayer standing upright around (200, 200), tilted by `angle` and with each lipid nudged a little
+    fn vertical_bilayer(angle: f32, nudge: f32) -> State {
+        let mut state = State::new();
+        let centre = Point::new(200.0, 200.0);
+        let (sin, cos) = angle.sin_cos();
+        let place = |x: f32, y: f32| centre + Vector::new(x * cos - y * sin, x * sin + y * cos);
+        for i in 0..30 {
+            let y = (i as f32 - 14.5) * 4.0 + nudge * ((i % 3) as f32 - 1.0);
+            // tails meet in the middle, heads point out to the left & right
+            for side in [-1.0, 1.0] {
+                state.add_lipid(Lipid {
+                    id: 0,
+                    species: 0,
+                    head_position: place(side * 11.0, y),
+                    tail_position: place(side * 1.0, y),
+                    linear_velocity: Vector::new(0.0, 0.0),
+                    angular_velocity: 0.0,
+                    head_radius: 2.0,
+                    tail_length: 10.0,
+                    tail_width: 1.5,
+                });
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn a_jittering_vertical_bilayer_has_no_flip_flops() {
+        // confirming straight away, so that any swap of the leaflets' names would count
+        let mut flip_flops = FlipFlops::new(1);
+        for frame in 0..20 {
+            // tilting either way of vertical flips the sign of the normal's y component
+            let sign = if frame % 2 == 0 { 1.0 } else { -1.0 };
+            let mut state = vertical_bilayer(sign * 0.05, 0.5 * sign);
+            state.tick = frame;
+            state.time = frame as f64;
+            flip_flops.update(&state);
+        }
+        assert!(flip_flops.rate().is_some(), "no bilayer found");
+        assert_eq!(flip_flops.events.len(), 0);
+    }
+}
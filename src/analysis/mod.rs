@@ -0,0 +1,71 @@
+//! Measurements of structure and dynamics, from single states or from whole trajectories.
+
+pub mod bilayer;
+pub mod clusters;
+pub mod diffusion;
+pub mod flipflop;
+pub mod order;
+pub mod rdf;
+pub mod undulation;
+
+use crate::types::*;
+
+/// Centre & spread of a set of points: the eigenvalues (largest first) of their gyration tensor, and the unit vector
+/// along which they're most spread out.
+#[derive(Debug, Copy, Clone)]
+pub struct Shape {
+    pub centroid: Point,
+    pub major: f32,
+    pub minor: f32,
+    pub major_axis: Vector,
+}
+
+impl Shape {
+    pub fn of(points: impl Iterator<Item = Point> + Clone) -> Self {
+        let n = points.clone().count().max(1) as f32;
+        let sum = points
+            .clone()
+            .fold(Vector::new(0.0, 0.0), |sum, p| sum + (p - Point::new(0.0, 0.0)));
+        let centroid = Point::new(0.0, 0.0) + sum / n;
+        let (mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0);
+        for p in points {
+            let d = p - centroid;
+            xx += d.x * d.x / n;
+            xy += d.x * d.y / n;
+            yy += d.y * d.y / n;
+        }
+        let mean = (xx + yy) / 2.0;
+        let spread = (((xx - yy) / 2.0_f32).powi(2) + xy * xy).sqrt();
+        let angle = 0.5 * (2.0 * xy).atan2(xx - yy);
+        Self {
+            centroid,
+            major: mean + spread,
+            minor: mean - spread,
+            major_axis: Vector::new(angle.cos(), angle.sin()),
+        }
+    }
+
+    /// 0 for round, 1 for a line
+    pub fn anisotropy(&self) -> f32 {
+        if self.major > 0.0 { 1.0 - self.minor / self.major } else { 0.0 }
+    }
+
+    pub fn minor_axis(&self) -> Vector {
+        Vector::new(-self.major_axis.y, self.major_axis.x)
+    }
+}
+
+/// Least-squares `(slope, intercept)`. `None` with fewer than two distinct x values.
+pub fn fit_line(points: impl Iterator<Item = (f64, f64)> + Clone) -> Option<(f64, f64)> {
+    let n = points.clone().count() as f64;
+    let (sx, sy) = points.clone().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    let (mx, my) = (sx / n, sy / n);
+    let (sxx, sxy) = points.fold((0.0, 0.0), |(sxx, sxy), (x, y)| {
+        (sxx + (x - mx) * (x - mx), sxy + (x - mx) * (y - my))
+    });
+    if n < 2.0 || sxx <= 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, my - slope * mx))
+}
@@ -0,0 +1,99 @@
+//! Orientational order of the tails: the 2D nematic order parameter and director, over everything or per grid cell.
+//!
+//! With θ the angle of each head->tail vector, `S = |<e^(2iθ)>|`: 1 when all lipids are parallel or antiparallel (as in a
+//! bilayer, or a gel), 0 when they point every which way. The director is the direction they line up along.
+
+use crate::observables::Observable;
+use crate::types::*;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Order {
+    pub s: f32,
+    /// Unit vector; `-director` is just as good
+    pub director: Vector,
+    pub count: usize,
+}
+
+pub fn nematic(directions: impl Iterator<Item = Vector>) -> Option<Order> {
+    let (mut cos2, mut sin2, mut count) = (0.0, 0.0, 0);
+    for d in directions {
+        let angle = d.y.atan2(d.x);
+        cos2 += (2.0 * angle).cos();
+        sin2 += (2.0 * angle).sin();
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+    let (cos2, sin2) = (cos2 / count as f32, sin2 / count as f32);
+    let director_angle = 0.5 * sin2.atan2(cos2);
+    Some(Order {
+        s: (cos2 * cos2 + sin2 * sin2).sqrt(),
+        director: Vector::new(director_angle.cos(), director_angle.sin()),
+        count,
+    })
+}
+
+pub fn global(state: &State) -> Option<Order> {
+    nematic(state.lipids.iter().map(Lipid::direction))
+}
+
+/// Local order on a grid laid over the box, from the origin to its upper corner, binning lipids by their midpoint.
+pub struct DirectorField {
+    pub cell_size: f32,
+    /// Indexed `[y, x]` like the water; `None` where there are no lipids
+    pub cells: ndarray::Array2<Option<Order>>,
+}
+
+impl DirectorField {
+    pub fn of(state: &State, bounds: (Point, Point), cell_size: f32) -> Self {
+        let shape = ((bounds.1.y / cell_size).ceil() as usize, (bounds.1.x / cell_size).ceil() as usize);
+        let mut directions = ndarray::Array2::from_elem(shape, vec![]);
+        for l in state.lipids.iter() {
+            let p = l.midpoint();
+            let (iy, ix) = ((p.y / cell_size).floor(), (p.x / cell_size).floor());
+            if iy >= 0.0 && ix >= 0.0 && (iy as usize) < shape.0 && (ix as usize) < shape.1 {
+                directions[(iy as usize, ix as usize)].push(l.direction());
+            }
+        }
+        Self {
+            cell_size,
+            cells: directions.map(|d| nematic(d.iter().copied())),
+        }
+    }
+
+    pub fn cell_centre(&self, iy: usize, ix: usize) -> Point {
+        Point::new((ix as f32 + 0.5) * self.cell_size, (iy as f32 + 0.5) * self.cell_size)
+    }
+
+    /// Mean `S` over the cells with at least `min_count` lipids. A single lipid is perfectly ordered by itself, so cells
+    /// with one lipid say little.
+    pub fn mean_order(&self, min_count: usize) -> Option<f32> {
+        let local: Vec<f32> = self.cells.iter().flatten().filter(|o| o.count >= min_count).map(|o| o.s).collect();
+        (!local.is_empty()).then(|| local.iter().sum::<f32>() / local.len() as f32)
+    }
+}
+
+/// Global nematic order & director, plus the mean local order on a grid
+pub struct NematicOrder {
+    pub cell_size: f32,
+}
+
+impl Observable for NematicOrder {
+    fn columns(&self) -> Vec<String> {
+        ["nematic_order", "director_x", "director_y", "local_nematic_order"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn measure(&mut self, state: &State, bounds: (Point, Point)) -> Vec<f64> {
+        let local = DirectorField::of(state, bounds, self.cell_size)
+            .mean_order(2)
+            .map_or(f64::NAN, |s| s as f64);
+        match global(state) {
+            Some(order) => vec![order.s as f64, order.director.x as f64, order.director.y as f64, local],
+            None => vec![f64::NAN, f64::NAN, f64::NAN, local],
+        }
+    }
+}
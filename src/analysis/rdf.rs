@@ -0,0 +1,145 @@
+//! Radial distribution functions g(r) between heads and tail points, accumulated over frames.
+//!
+//! "Tail" beads are all the points along the tail that the engine uses (`TAIL_POINTS`). Pairs within the same lipid are
+//! left out, so g(r) only shows how lipids pack against each other. The bounds aren't periodic, so g(r) sags at r
+//! comparable to the box, where part of each shell is outside it.
+
+use crate::engine::TAIL_POINTS;
+use crate::types::*;
+use std::f64::consts::PI;
+use std::io::{self, Write};
+
+/// Most bins `Rdf::new` makes, to keep a tiny bin width from taking all the memory
+const MAX_BINS: usize = 1_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pair {
+    HeadHead,
+    HeadTail,
+    TailTail,
+}
+
+impl Pair {
+    pub const ALL: [Pair; 3] = [Pair::HeadHead, Pair::HeadTail, Pair::TailTail];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pair::HeadHead => "head_head",
+            Pair::HeadTail => "head_tail",
+            Pair::TailTail => "tail_tail",
+        }
+    }
+}
+
+pub struct Rdf {
+    bin_width: f32,
+    /// Per pair type, per bin: number of pairs counted, summed over frames
+    histograms: [Vec<u64>; 3],
+    /// Per pair type: the ideal-gas number of pairs per unit area, summed over frames
+    ideal_densities: [f64; 3],
+    frames: usize,
+}
+
+impl Rdf {
+    /// Fails unless `bin_width` and `max_r` are positive numbers, with at most `MAX_BINS` bins between them.
+    pub fn new(bin_width: f32, max_r: f32) -> io::Result<Self> {
+        let invalid_input = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        for (name, value) in [("bin width", bin_width), ("max r", max_r)] {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(invalid_input(format!("the {name} has to be a positive number, not {value}")));
+            }
+        }
+        let num_bins = (max_r / bin_width).ceil();
+        if num_bins > MAX_BINS as f32 {
+            return Err(invalid_input(format!("{num_bins} bins is too many, at most {MAX_BINS}")));
+        }
+        let num_bins = num_bins as usize;
+        Ok(Self {
+            bin_width,
+            histograms: [vec![0; num_bins], vec![0; num_bins], vec![0; num_bins]],
+            ideal_densities: [0.0; 3],
+            frames: 0,
+        })
+    }
+
+    pub fn accumulate(&mut self, state: &State, bounds: (Point, Point)) {
+        let size = bounds.1 - bounds.0;
+        let area = (size.x * size.y) as f64;
+        let heads: Vec<Point> = state.lipids.iter().map(|l| l.head_position).collect();
+        let tails: Vec<Vec<Point>> = state
+            .lipids
+            .iter()
+            .map(|l| TAIL_POINTS.iter().map(|frac| l.tail_point(*frac)).collect())
+            .collect();
+        let num_heads = heads.len() as f64;
+        let num_tails = (heads.len() * TAIL_POINTS.len()) as f64;
+
+        // counting each unordered pair once, so the ideal numbers are per unordered pair too
+        let max_r2 = (self.bin_width * self.histograms[0].len() as f32).powi(2);
+        let bin_width = self.bin_width;
+        let mut count = |pair: Pair, a: Point, b: Point| {
+            let r2 = a.distance2(b);
+            // rounding can put r just under max_r in the bin past the last
+            if r2 < max_r2
+                && let Some(n) = self.histograms[pair as usize].get_mut((r2.sqrt() / bin_width) as usize)
+            {
+                *n += 1;
+            }
+        };
+        for i in 0..heads.len() {
+            for j in 0..heads.len() {
+                if i == j {
+                    continue;
+                }
+                for t in tails[j].iter() {
+                    count(Pair::HeadTail, heads[i], *t);
+                }
+                if j < i {
+                    continue;
+                }
+                count(Pair::HeadHead, heads[i], heads[j]);
+                for ti in tails[i].iter() {
+                    for tj in tails[j].iter() {
+                        count(Pair::TailTail, *ti, *tj);
+                    }
+                }
+            }
+        }
+
+        let per_lipid = TAIL_POINTS.len() as f64;
+        self.ideal_densities[Pair::HeadHead as usize] += num_heads * (num_heads - 1.0) / 2.0 / area;
+        self.ideal_densities[Pair::HeadTail as usize] += num_heads * (num_tails - per_lipid) / area;
+        self.ideal_densities[Pair::TailTail as usize] += num_tails * (num_tails - per_lipid) / 2.0 / area;
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// g(r) at the middle of each bin
+    pub fn g(&self, pair: Pair) -> Vec<f64> {
+        let bin_width = self.bin_width as f64;
+        self.histograms[pair as usize]
+            .iter()
+            .enumerate()
+            .map(|(ibin, count)| {
+                let shell_area = PI * bin_width * bin_width * ((ibin + 1).pow(2) - ibin.pow(2)) as f64;
+                let ideal = self.ideal_densities[pair as usize] * shell_area;
+                if ideal > 0.0 { *count as f64 / ideal } else { 0.0 }
+            })
+            .collect()
+    }
+
+    pub fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        let gs: Vec<_> = Pair::ALL.iter().map(|pair| self.g(*pair)).collect();
+        let names: Vec<_> = Pair::ALL.iter().map(|pair| format!("g_{}", pair.name())).collect();
+        writeln!(w, "r,{}", names.join(","))?;
+        for ibin in 0..self.histograms[0].len() {
+            let r = (ibin as f32 + 0.5) * self.bin_width;
+            let values: Vec<_> = gs.iter().map(|g| g[ibin].to_string()).collect();
+            writeln!(w, "{},{}", r, values.join(","))?;
+        }
+        Ok(())
+    }
+}
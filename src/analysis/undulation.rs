@@ -0,0 +1,231 @@
+//! Shape fluctuations of a bilayer: its midplane contour, the contour's curvature, and the Fourier spectrum of its
+//! undulations, from which the bending rigidity and tension follow.
+//!
+//! For a membrane line of length `L` with bending rigidity `κ` and tension `σ`, equipartition gives
+//! `L <|h_q|²> = k_B T / (σ q² + κ q⁴)` for each mode `q = 2πn / L`, with `h_q = (1/N) Σ h(s) e^(-iqs)`. So
+//! `k_B T / (L <|h_q|²> q²)` against `q²` is a line with slope `κ` and intercept `σ`. `k_B T` is the equipartition
+//! temperature of the lipids (k_B = 1). This is only meaningful for a bilayer that spans the box; a patch with free
+//! edges fluctuates in ways the formula doesn't describe.
+
+use super::bilayer::{self, Bilayer};
+use super::fit_line;
+use crate::observables::Temperature;
+use crate::types::*;
+use std::f64::consts::PI;
+use std::io::{self, Write};
+
+/// Roughly two lipids' worth of membrane per contour point, so every point has some lipids to average over.
+pub const CONTOUR_BIN_LENGTH: f32 = 10.0;
+
+/// The midplane height above a straight reference line, sampled evenly along it
+#[derive(Debug, Clone)]
+pub struct Contour {
+    /// Where the reference line starts
+    pub origin: Point,
+    /// Unit vector along the reference line
+    pub along: Vector,
+    /// Unit vector the heights are measured along
+    pub normal: Vector,
+    pub length: f32,
+    /// At the middle of each of the evenly spaced bins
+    pub heights: Vec<f32>,
+}
+
+impl Contour {
+    /// Samples the bilayer midplane (where the tails of the two leaflets meet) in `num_bins` bins over the length of
+    /// the bilayer. Bins with no lipids get heights interpolated from their neighbours. `None` if no bin has lipids.
+    pub fn of(state: &State, bilayer: &Bilayer, num_bins: usize) -> Option<Self> {
+        let normal = bilayer.normal;
+        let along = Vector::new(normal.y, -normal.x);
+        let samples: Vec<(f32, f32)> = bilayer
+            .leaflets
+            .iter()
+            .map(|(i, _)| {
+                let offset = state.lipids[*i].tail_position - bilayer.midplane_point;
+                (offset.dot(along), offset.dot(normal))
+            })
+            .collect();
+        let start = samples.iter().map(|(s, _)| *s).fold(f32::INFINITY, f32::min);
+        let end = samples.iter().map(|(s, _)| *s).fold(f32::NEG_INFINITY, f32::max);
+        let length = end - start;
+        if num_bins == 0 || length.is_nan() || length <= 0.0 {
+            return None;
+        }
+
+        let mut sums = vec![(0.0, 0); num_bins];
+        for (s, h) in samples {
+            let bin = (((s - start) / length * num_bins as f32) as usize).min(num_bins - 1);
+            sums[bin].0 += h;
+            sums[bin].1 += 1;
+        }
+        let known: Vec<(usize, f32)> = sums
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, n))| *n > 0)
+            .map(|(ibin, (sum, n))| (ibin, sum / *n as f32))
+            .collect();
+        if known.is_empty() {
+            return None;
+        }
+        let heights = (0..num_bins)
+            .map(|ibin| {
+                let after = known.iter().position(|(k, _)| *k >= ibin);
+                match after {
+                    Some(0) => known[0].1,
+                    None => known[known.len() - 1].1,
+                    Some(a) => {
+                        let ((k0, h0), (k1, h1)) = (known[a - 1], known[a]);
+                        h0 + (h1 - h0) * (ibin - k0) as f32 / (k1 - k0) as f32
+                    }
+                }
+            })
+            .collect();
+
+        Some(Self {
+            origin: bilayer.midplane_point + along * start,
+            along,
+            normal,
+            length,
+            heights,
+        })
+    }
+
+    /// With as many bins as make them about `CONTOUR_BIN_LENGTH` long (at least one)
+    pub fn binned(state: &State, bilayer: &Bilayer) -> Option<Self> {
+        let length = Self::of(state, bilayer, 1)?.length;
+        Self::of(state, bilayer, ((length / CONTOUR_BIN_LENGTH).round() as usize).max(1))
+    }
+
+    pub fn bin_length(&self) -> f32 {
+        self.length / self.heights.len() as f32
+    }
+
+    pub fn point(&self, ibin: usize) -> Point {
+        self.origin + self.along * ((ibin as f32 + 0.5) * self.bin_length()) + self.normal * self.heights[ibin]
+    }
+
+    /// Signed curvature at each bin, from finite differences; the ends, which lack a neighbour, get 0.
+    pub fn curvature(&self) -> Vec<f32> {
+        let ds = self.bin_length();
+        let h = &self.heights;
+        (0..h.len())
+            .map(|i| {
+                if i == 0 || i + 1 == h.len() {
+                    return 0.0;
+                }
+                let slope = (h[i + 1] - h[i - 1]) / (2.0 * ds);
+                let second = (h[i + 1] - 2.0 * h[i] + h[i - 1]) / (ds * ds);
+                second / (1.0 + slope * slope).powf(1.5)
+            })
+            .collect()
+    }
+
+    /// `|h_q|²` for the modes `n = 1 ..= N/2`, with the mean height (`n = 0`) left out
+    pub fn power_spectrum(&self) -> Vec<f64> {
+        let n = self.heights.len();
+        (1..=n / 2)
+            .map(|mode| {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (k, h) in self.heights.iter().enumerate() {
+                    let phase = 2.0 * PI * (mode * k) as f64 / n as f64;
+                    re += *h as f64 * phase.cos();
+                    im -= *h as f64 * phase.sin();
+                }
+                (re * re + im * im) / (n * n) as f64
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Mechanics {
+    pub bending_rigidity: f64,
+    pub tension: f64,
+}
+
+/// Averages the undulation spectrum of the largest bilayer over frames. The number of contour bins is fixed by the
+/// first frame with a bilayer, so that each mode means the same thing in every frame.
+#[derive(Default)]
+pub struct Undulations {
+    num_bins: usize,
+    power_sums: Vec<f64>,
+    length_sum: f64,
+    temperature_sum: f64,
+    frames: usize,
+}
+
+impl Undulations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `false` if the state had no bilayer to measure.
+    pub fn accumulate(&mut self, state: &State) -> bool {
+        let Some(bilayer) = bilayer::largest(state) else {
+            return false;
+        };
+        if self.num_bins == 0 {
+            let num_bins = Contour::binned(state, &bilayer).map_or(0, |c| c.heights.len());
+            if num_bins < 4 {
+                return false;
+            }
+            self.num_bins = num_bins;
+            self.power_sums = vec![0.0; num_bins / 2];
+        }
+        let Some(contour) = Contour::of(state, &bilayer, self.num_bins) else {
+            return false;
+        };
+        for (sum, power) in self.power_sums.iter_mut().zip(contour.power_spectrum()) {
+            *sum += power;
+        }
+        self.length_sum += contour.length as f64;
+        self.temperature_sum += Temperature::of(state);
+        self.frames += 1;
+        true
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// `(q, <|h_q|²>)` per mode
+    pub fn spectrum(&self) -> Vec<(f64, f64)> {
+        if self.frames == 0 {
+            return vec![];
+        }
+        let length = self.length_sum / self.frames as f64;
+        self.power_sums
+            .iter()
+            .enumerate()
+            .map(|(imode, sum)| (2.0 * PI * (imode + 1) as f64 / length, sum / self.frames as f64))
+            .collect()
+    }
+
+    /// `None` with fewer than two modes, or a spectrum that doesn't fit `σ q² + κ q⁴` with positive `κ`.
+    pub fn fit(&self) -> Option<Mechanics> {
+        if self.frames == 0 {
+            return None;
+        }
+        let length = self.length_sum / self.frames as f64;
+        let temperature = self.temperature_sum / self.frames as f64;
+        let spectrum = self.spectrum();
+        let (slope, intercept) = fit_line(
+            spectrum
+                .iter()
+                .filter(|(_, power)| *power > 0.0)
+                .map(|(q, power)| (q * q, temperature / (length * power * q * q))),
+        )?;
+        (slope > 0.0).then_some(Mechanics {
+            bending_rigidity: slope,
+            tension: intercept,
+        })
+    }
+
+    pub fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "mode,q,power")?;
+        for (imode, (q, power)) in self.spectrum().iter().enumerate() {
+            writeln!(w, "{},{},{}", imode + 1, q, power)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use macrolipid::analysis;
+use macrolipid::mltraj;
+use macrolipid::observables;
+
+/// Knobs for the analyses that have them
+pub struct Options {
+    pub bin_width: f32,
+    pub max_r: f32,
+    /// In frames; `None` for half the trajectory
+    pub max_lag: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            bin_width: 0.25,
+            max_r: 30.0,
+            max_lag: None,
+        }
+    }
+}
+
+/// Goes through a recorded `.mltraj`, writing the named analysis of it as CSV. Any observable works too, giving its
+/// time series.
+pub fn run(name: &str, input: &Path, output: &Path, options: &Options) -> io::Result<()> {
+    let mut reader = mltraj::Reader::open(input)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    match name {
+        "clusters" => {
+            writeln!(out, "tick,cluster,size,kind,anisotropy,centroid_x,centroid_y")?;
+            for iframe in 0..reader.len() {
+                let (state, _) = reader.read_frame(iframe)?;
+                for (icluster, c) in analysis::clusters::analyze(&state).iter().enumerate() {
+                    writeln!(
+                        out,
+                        "{},{},{},{},{},{},{}",
+                        state.tick,
+                        icluster,
+                        c.lipids.len(),
+                        c.kind.name(),
+                        c.shape.anisotropy(),
+                        c.shape.centroid.x,
+                        c.shape.centroid.y
+                    )?;
+                }
+            }
+        }
+        "rdf" => {
+            let mut rdf = analysis::rdf::Rdf::new(options.bin_width, options.max_r)?;
+            for iframe in 0..reader.len() {
+                let (state, bounds) = reader.read_frame(iframe)?;
+                rdf.accumulate(&state, bounds);
+            }
+            rdf.write_csv(&mut out)?;
+        }
+        "msd" => {
+            let mut diffusion = analysis::diffusion::Diffusion::new();
+            for iframe in 0..reader.len() {
+                diffusion.add_frame(&reader.read_frame(iframe)?.0);
+            }
+            let max_lag = options.max_lag.unwrap_or(diffusion.frames() / 2);
+            diffusion.write_csv(&mut out, max_lag)?;
+            let correlations = diffusion.correlations(max_lag);
+            let show = |d: Option<f64>| d.map_or("n/a (too few frames)".to_string(), |d| d.to_string());
+            println!(
+                "lateral diffusion coefficient: {}",
+                show(analysis::diffusion::lateral_coefficient(&correlations))
+            );
+            println!(
+                "rotational diffusion coefficient: {}",
+                show(analysis::diffusion::rotational_coefficient(&correlations))
+            );
+        }
+        "contour" => {
+            writeln!(out, "tick,bin,x,y,height,curvature")?;
+            for iframe in 0..reader.len() {
+                let (state, _) = reader.read_frame(iframe)?;
+                let Some(bilayer) = analysis::bilayer::largest(&state) else {
+                    continue;
+                };
+                if let Some(contour) = analysis::undulation::Contour::binned(&state, &bilayer) {
+                    for (ibin, curvature) in contour.curvature().iter().enumerate() {
+                        let p = contour.point(ibin);
+                        writeln!(
+                            out,
+                            "{},{},{},{},{},{}",
+                            state.tick, ibin, p.x, p.y, contour.heights[ibin], curvature
+                        )?;
+                    }
+                }
+            }
+        }
+        "undulations" => {
+            // the fit needs the temperature, and quantized frames don't keep the velocities it comes from
+            if reader.header().quantized {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "undulations need velocities for the temperature, which quantized trajectories don't keep",
+                ));
+            }
+            let mut undulations = analysis::undulation::Undulations::new();
+            for iframe in 0..reader.len() {
+                undulations.accumulate(&reader.read_frame(iframe)?.0);
+            }
+            undulations.write_csv(&mut out)?;
+            match undulations.fit() {
+                Some(fit) => {
+                    println!("bending rigidity: {}", fit.bending_rigidity);
+                    println!("tension: {}", fit.tension);
+                }
+                None => println!(
+                    "no fit: {} frames with a bilayer spanning enough contour bins",
+                    undulations.frames()
+                ),
+            }
+        }
+        "flip_flops" => {
+            let mut flip_flops = analysis::flipflop::FlipFlops::default();
+            for iframe in 0..reader.len() {
+                flip_flops.update(&reader.read_frame(iframe)?.0);
+            }
+            flip_flops.write_csv(&mut out)?;
+            println!(
+                "{} flip-flops ({} upper->lower, {} lower->upper)",
+                flip_flops.events.len(),
+                flip_flops.count(analysis::bilayer::Leaflet::Upper),
+                flip_flops.count(analysis::bilayer::Leaflet::Lower)
+            );
+            match flip_flops.rate() {
+                Some(rate) => println!("rate: {rate} per lipid per unit time"),
+                None => println!("rate: n/a (no bilayer over time)"),
+            }
+        }
+        "local_thickness" => {
+            writeln!(out, "tick,bin,thickness")?;
+            for iframe in 0..reader.len() {
+                let (state, _) = reader.read_frame(iframe)?;
+                if let Some(bilayer) = analysis::bilayer::largest(&state) {
+                    for (ibin, thickness) in bilayer.local_thickness.iter().enumerate() {
+                        writeln!(out, "{},{},{}", state.tick, ibin, thickness)?;
+                    }
+                }
+            }
+        }
+        _ => {
+            let Some(observable) = observables::by_name(name) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown analysis {name}")));
+            };
+            let mut log = observables::ObservableLog::new(observables::LogFormat::Csv, out, vec![observable], false);
+            for iframe in 0..reader.len() {
+                let (state, bounds) = reader.read_frame(iframe)?;
+                log.log(&state, bounds)?;
+            }
+            return Ok(());
+        }
+    }
+    out.flush()
+}
@@ -2,11 +2,35 @@ use opengl_graphics::{GlGraphics, GlyphCache, OpenGL, Texture, TextureSettings};
 use piston::input::{RenderArgs, UpdateArgs};
 use std::time::Duration;
 
-use crate::types::*;
+use macrolipid::analysis::order::DirectorField;
+use macrolipid::engine::{self, SimError};
+use macrolipid::geometry::{Obstacle, Shape, Surface};
+use macrolipid::types::*;
+
+/// By species, cycling if there are more species than colours
+const TAIL_COLOURS: [graphics::types::Color; 4] = [
+    graphics::color::GREEN,
+    graphics::color::CYAN,
+    graphics::color::PURPLE,
+    graphics::color::LIME,
+];
+const HEAD_COLOURS: [graphics::types::Color; 4] = [
+    graphics::color::RED,
+    graphics::color::YELLOW,
+    graphics::color::MAGENTA,
+    graphics::color::MAROON,
+];
 
 pub struct App<'a> {
     gl: GlGraphics,
     state: State,
+    /// The box `state` is in
+    bounds: (Point, Point),
+    container: Option<Shape>,
+    obstacles: Vec<Obstacle>,
+    error: Option<SimError>,
+    status: String,
+    show_director_field: bool,
     glyph_cache: GlyphCache<'a>,
     debug_texture0: Texture,
 }
@@ -16,6 +40,12 @@ impl App<'_> {
         Self {
             gl: GlGraphics::new(OpenGL::V4_2),
             state: State::new(),
+            bounds: engine::DEFAULT_BOUNDS,
+            container: None,
+            obstacles: vec![],
+            error: None,
+            status: String::new(),
+            show_director_field: false,
             glyph_cache: GlyphCache::new("/usr/share/fonts/TTF/DejaVuSans.ttf", (), TextureSettings::new()).unwrap(),
             debug_texture0: opengl_graphics::CreateTexture::create(
                 &mut (),
@@ -33,18 +63,37 @@ impl App<'_> {
         use graphics::*;
 
         let state = &self.state;
+        let container = &self.container;
+        let obstacles = &self.obstacles;
+        let error = &self.error;
+        let status = &self.status;
+        let director_field = self.show_director_field.then(|| DirectorField::of(state, self.bounds, 20.0));
         let glyph_cache = &mut self.glyph_cache;
         let debug_texture0 = &mut self.debug_texture0;
 
-        ::opengl_graphics::UpdateTexture::update(
-            debug_texture0,
-            &mut (),
-            ::opengl_graphics::Format::Rgba8,
-            state.debug_array0.as_slice().unwrap(),
-            [0, 0],
-            [400, 400],
-        )
-        .unwrap();
+        // the water grid grows & shrinks with the bounds
+        let (rows, columns, _) = state.debug_array0.dim();
+        let size = [columns as u32, rows as u32];
+        if debug_texture0.get_size() == (size[0], size[1]) {
+            ::opengl_graphics::UpdateTexture::update(
+                debug_texture0,
+                &mut (),
+                ::opengl_graphics::Format::Rgba8,
+                state.debug_array0.as_slice().unwrap(),
+                [0, 0],
+                size,
+            )
+            .unwrap();
+        } else {
+            *debug_texture0 = opengl_graphics::CreateTexture::create(
+                &mut (),
+                opengl_graphics::Format::Rgba8,
+                state.debug_array0.as_slice().unwrap(),
+                size,
+                &TextureSettings::new(),
+            )
+            .unwrap();
+        }
 
         self.gl.draw(args.viewport(), |c, gl| {
             let scale = 2.5;
@@ -53,9 +102,57 @@ impl App<'_> {
             clear(BLACK, gl);
             ::graphics::image(debug_texture0, objects_transform, gl);
 
-            for (i_lipid, lipid) in state.lipids.iter().enumerate() {
+            let outline = WHITE.mul_rgba(1.0, 1.0, 1.0, 0.6);
+            match container {
+                Some(Shape::Circle { centre, radius }) => Ellipse::new_border(outline, 0.5).draw(
+                    ellipse::circle(centre.x as f64, centre.y as f64, *radius as f64),
+                    &DrawState::default(),
+                    objects_transform,
+                    gl,
+                ),
+                Some(Shape::Polygon(corners)) => {
+                    for (a, b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                        line(
+                            outline,
+                            0.5,
+                            [a.x as f64, a.y as f64, b.x as f64, b.y as f64],
+                            objects_transform,
+                            gl,
+                        );
+                    }
+                }
+                Some(Shape::Segment(..)) | None => (),
+            }
+
+            for obstacle in obstacles.iter() {
+                let colour = match obstacle.surface {
+                    Surface::Hydrophilic => [0.3, 0.5, 1.0, 0.8],
+                    Surface::Hydrophobic => [1.0, 0.6, 0.2, 0.8],
+                };
+                match &obstacle.shape {
+                    Shape::Segment(a, b) => line(colour, 0.5, [a.x as f64, a.y as f64, b.x as f64, b.y as f64], objects_transform, gl),
+                    Shape::Circle { centre, radius } => ellipse(
+                        colour,
+                        ellipse::circle(centre.x as f64, centre.y as f64, *radius as f64),
+                        objects_transform,
+                        gl,
+                    ),
+                    Shape::Polygon(corners) => {
+                        let corners: Vec<[f64; 2]> = corners.iter().map(|p| [p.x as f64, p.y as f64]).collect();
+                        polygon(colour, &corners, objects_transform, gl);
+                    }
+                }
+            }
+
+            // species picks the colour, and the id a shade of it, so a lipid looks the same wherever it is in the list
+            let shade = |id: &LipidId| *id as f32 / 1.5 / state.next_id.max(1) as f32;
+
+            for lipid in state.lipids.iter() {
+                #[allow(clippy::match_single_binding)]
                 match lipid {
                     Lipid {
+                        id,
+                        species,
                         head_position,
                         tail_position,
                         linear_velocity: _,
@@ -65,8 +162,8 @@ impl App<'_> {
                         tail_width,
                     } => {
                         line(
-                            GREEN
-                                .shade(i_lipid as f32 / 1.5 / state.lipids.len() as f32)
+                            TAIL_COLOURS[*species as usize % TAIL_COLOURS.len()]
+                                .shade(shade(id))
                                 .mul_rgba(1.0, 1.0, 1.0, 0.5),
                             *tail_width as f64,
                             [
@@ -83,9 +180,12 @@ impl App<'_> {
             }
 
             // render heads after, since they are small
-            for (i_lipid, lipid) in state.lipids.iter().enumerate() {
+            for lipid in state.lipids.iter() {
+                #[allow(clippy::match_single_binding)]
                 match lipid {
                     Lipid {
+                        id,
+                        species,
                         head_position,
                         tail_position: _,
                         linear_velocity: _,
@@ -101,7 +201,8 @@ impl App<'_> {
                             *head_radius as f64,
                         ]);
                         rectangle(
-                            RED.shade(i_lipid as f32 / 1.5 / state.lipids.len() as f32)
+                            HEAD_COLOURS[*species as usize % HEAD_COLOURS.len()]
+                                .shade(shade(id))
                                 .mul_rgba(1.0, 1.0, 1.0, 0.5),
                             square,
                             objects_transform,
@@ -111,6 +212,27 @@ impl App<'_> {
                 }
             }
 
+            // local nematic director: longer is more ordered
+            if let Some(field) = &director_field {
+                for ((iy, ix), order) in field.cells.indexed_iter() {
+                    let Some(order) = order else { continue };
+                    let centre = field.cell_centre(iy, ix);
+                    let half = order.director * order.s * field.cell_size * 0.45;
+                    line(
+                        YELLOW.mul_rgba(1.0, 1.0, 1.0, 0.8),
+                        0.5,
+                        [
+                            (centre.x - half.x) as f64,
+                            (centre.y - half.y) as f64,
+                            (centre.x + half.x) as f64,
+                            (centre.y + half.y) as f64,
+                        ],
+                        objects_transform,
+                        gl,
+                    );
+                }
+            }
+
             let min_frame_time = Duration::new(0, (1_000_000_000.0 / max_fps as f64) as u32);
             text::Text::new_color(WHITE.mul_rgba(1.0, 1.0, 1.0, 0.4), 16)
                 .draw(
@@ -132,11 +254,59 @@ impl App<'_> {
                     gl,
                 )
                 .unwrap();
+
+            let time_step = state.time_step;
+            text::Text::new_color(WHITE.mul_rgba(1.0, 1.0, 1.0, 0.4), 16)
+                .draw(
+                    &format!("Time Step: {time_step:e}"),
+                    glyph_cache,
+                    &DrawState::default(),
+                    c.transform.trans(0.0, 48.0),
+                    gl,
+                )
+                .unwrap();
+
+            text::Text::new_color(WHITE.mul_rgba(1.0, 1.0, 1.0, 0.4), 16)
+                .draw(status, glyph_cache, &DrawState::default(), c.transform.trans(0.0, 64.0), gl)
+                .unwrap();
+
+            if let Some(error) = error {
+                text::Text::new_color(RED, 16)
+                    .draw(
+                        &format!("Stopped: {error}"),
+                        glyph_cache,
+                        &DrawState::default(),
+                        c.transform.trans(0.0, 80.0),
+                        gl,
+                    )
+                    .unwrap();
+            }
         });
     }
 
-    pub fn new_data(&mut self, state: State) {
+    /// Drawn under the lipids; they don't come with the states, since they never change.
+    pub fn set_walls(&mut self, container: Option<Shape>, obstacles: Vec<Obstacle>) {
+        self.container = container;
+        self.obstacles = obstacles;
+    }
+
+    pub fn new_data(&mut self, state: State, bounds: (Point, Point)) {
         self.state = state;
+        self.bounds = bounds;
+    }
+
+    /// The engine gave up; keep showing the last state along with why.
+    pub fn new_error(&mut self, error: SimError) {
+        self.error = Some(error);
+    }
+
+    pub fn toggle_director_field(&mut self) {
+        self.show_director_field = !self.show_director_field;
+    }
+
+    /// One line of whatever is driving the display wants to say, e.g. where a replay is at.
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
     }
 
     pub fn update(&mut self, _args: &UpdateArgs) {}
@@ -0,0 +1,494 @@
+//! Binary snapshots of everything the engine needs to carry on exactly where it left off.
+//!
+//! Everything is little-endian, and floats are stored bit-for-bit:
+//! ```text
+//! magic "MLCKPT\0\0" | version u32 | rng seed [u8; 32] | Settings | bounds (4 x f32) | container | obstacle count u32
+//!   | obstacles | fields | prev State | curr State
+//! Settings: time step kind u8 (0 fixed, 1 adaptive) | step f32 (fixed) or max displacement, min & max (3 x f32, adaptive)
+//!           | max jump f32 | min time step f32 | max bond stretch f32 | seed u64 | has barostat u8 | Barostat (if it has one)
+//! Barostat: target f32 | coupling f32 | axes u8 (0 both, 1 x, 2 y)
+//! Fields: gravity (2 x f32) | electric field (2 x f32) | shear f32 | charge count u32 | charges (f32 each) | has pull u8
+//!         | Pull (if it has one)
+//! Pull: lipid count u32 | lipid ids (u32 each) | anchor (2 x f32) | velocity (2 x f32) | stiffness f32
+//! Container: has container u8 | Shape (if it has one)
+//! Obstacle: surface u8 (0 hydrophilic, 1 hydrophobic) | Shape
+//! Shape: kind u8 (0 segment, 1 circle, 2 polygon) | point count u32 | points (2 x f32 each) | radius f32 (circles only)
+//! State: tick u64 | time f64 | time_step f32 | next lipid id u32 | lipid count u64 | lipids | tag count u32 | tags
+//!        | restraint count u32 | restraints
+//! Lipid: id u32 | species u16 | head (2 x f32) | tail (2 x f32) | linear velocity (2 x f32) | angular velocity | head radius
+//!        | tail length | tail width
+//! Tag: lipid id u32 | length u32 | UTF-8
+//! Restraint: lipid id u32 | kind u8 (0 frozen, 1 harmonic) | head (2 x f32) | tail (2 x f32) | stiffness f32, the last
+//!            three for harmonic ones only
+//! ```
+
+use crate::engine::{Barostat, BoxAxes, Fields, Pull, Settings, TimeStep};
+use crate::geometry::{Obstacle, Shape, Surface};
+use crate::types::*;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 8] = b"MLCKPT\0\0";
+const VERSION: u32 = 1;
+
+pub struct Checkpoint {
+    pub seed: [u8; 32],
+    pub settings: Settings,
+    pub bounds: (Point, Point),
+    pub container: Option<Shape>,
+    pub obstacles: Vec<Obstacle>,
+    pub fields: Fields,
+    pub prev: State,
+    pub curr: State,
+}
+
+/// What `write` saves: a `Checkpoint`, borrowed from the engine
+pub struct Snapshot<'a> {
+    pub seed: &'a [u8; 32],
+    pub settings: &'a Settings,
+    pub bounds: (Point, Point),
+    pub container: Option<&'a Shape>,
+    pub obstacles: &'a [Obstacle],
+    pub fields: &'a Fields,
+    pub prev: &'a State,
+    pub curr: &'a State,
+}
+
+pub fn write(w: &mut impl Write, snapshot: &Snapshot) -> io::Result<()> {
+    let &Snapshot {
+        seed,
+        settings,
+        bounds,
+        container,
+        obstacles,
+        fields,
+        prev,
+        curr,
+    } = snapshot;
+    w.write_all(MAGIC)?;
+    write_u32(w, VERSION)?;
+    w.write_all(seed)?;
+    write_settings(w, settings)?;
+    write_point(w, bounds.0)?;
+    write_point(w, bounds.1)?;
+    write_walls(w, container, obstacles)?;
+    write_fields(w, fields)?;
+    write_state(w, prev)?;
+    write_state(w, curr)?;
+    w.flush()
+}
+
+pub fn read(r: &mut impl Read) -> io::Result<Checkpoint> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a macrolipid checkpoint"));
+    }
+    let version = read_u32(r)?;
+    if version != VERSION {
+        return Err(invalid_data(&format!("unsupported checkpoint version {version}")));
+    }
+    let mut seed = [0u8; 32];
+    r.read_exact(&mut seed)?;
+    let settings = read_settings(r)?;
+    let bounds = (read_point(r)?, read_point(r)?);
+    let (container, obstacles) = read_walls(r)?;
+    let fields = read_fields(r)?;
+    Ok(Checkpoint {
+        seed,
+        settings,
+        bounds,
+        container,
+        obstacles,
+        fields,
+        prev: read_state(r)?,
+        curr: read_state(r)?,
+    })
+}
+
+fn write_settings(w: &mut impl Write, settings: &Settings) -> io::Result<()> {
+    match settings.time_step {
+        TimeStep::Fixed(step) => {
+            w.write_all(&[0])?;
+            write_f32(w, step)?;
+        }
+        TimeStep::Adaptive {
+            max_displacement,
+            min,
+            max,
+        } => {
+            w.write_all(&[1])?;
+            write_f32(w, max_displacement)?;
+            write_f32(w, min)?;
+            write_f32(w, max)?;
+        }
+    }
+    write_f32(w, settings.max_jump)?;
+    write_f32(w, settings.min_time_step)?;
+    write_f32(w, settings.max_bond_stretch)?;
+    write_u64(w, settings.seed)?;
+    w.write_all(&[settings.barostat.is_some() as u8])?;
+    if let Some(barostat) = settings.barostat {
+        write_f32(w, barostat.target)?;
+        write_f32(w, barostat.coupling)?;
+        let axes = match barostat.axes {
+            BoxAxes::Both => 0,
+            BoxAxes::X => 1,
+            BoxAxes::Y => 2,
+        };
+        w.write_all(&[axes])?;
+    }
+    Ok(())
+}
+
+fn read_settings(r: &mut impl Read) -> io::Result<Settings> {
+    let time_step = match read_u8(r)? {
+        0 => TimeStep::Fixed(read_f32(r)?),
+        1 => TimeStep::Adaptive {
+            max_displacement: read_f32(r)?,
+            min: read_f32(r)?,
+            max: read_f32(r)?,
+        },
+        _ => return Err(invalid_data("bad time step kind")),
+    };
+    let max_jump = read_f32(r)?;
+    let min_time_step = read_f32(r)?;
+    let max_bond_stretch = read_f32(r)?;
+    let seed = read_u64(r)?;
+    let barostat = if read_u8(r)? != 0 {
+        let target = read_f32(r)?;
+        let coupling = read_f32(r)?;
+        let axes = match read_u8(r)? {
+            0 => BoxAxes::Both,
+            1 => BoxAxes::X,
+            2 => BoxAxes::Y,
+            _ => return Err(invalid_data("bad barostat axes")),
+        };
+        Some(Barostat { target, coupling, axes })
+    } else {
+        None
+    };
+    let settings = Settings {
+        time_step,
+        max_jump,
+        min_time_step,
+        max_bond_stretch,
+        seed,
+        barostat,
+    };
+    settings.check().map_err(|err| invalid_data(&err))?;
+    Ok(settings)
+}
+
+fn write_state(w: &mut impl Write, state: &State) -> io::Result<()> {
+    write_u64(w, state.tick)?;
+    write_f64(w, state.time)?;
+    write_f32(w, state.time_step)?;
+    write_u32(w, state.next_id)?;
+    write_u64(w, state.lipids.len() as u64)?;
+    for l in state.lipids.iter() {
+        write_identity(w, l)?;
+        write_lipid(w, l)?;
+    }
+    write_tags(w, state)?;
+    write_u32(w, state.restraints.len() as u32)?;
+    for (id, restraint) in state.restraints.iter() {
+        write_u32(w, *id)?;
+        match restraint {
+            Restraint::Frozen => w.write_all(&[0])?,
+            Restraint::Harmonic { head, tail, stiffness } => {
+                w.write_all(&[1])?;
+                write_point(w, *head)?;
+                write_point(w, *tail)?;
+                write_f32(w, *stiffness)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_state(r: &mut impl Read) -> io::Result<State> {
+    let mut state = State::new();
+    state.tick = read_u64(r)?;
+    state.time = read_f64(r)?;
+    state.time_step = read_f32(r)?;
+    state.next_id = read_u32(r)?;
+    let num_lipids = read_u64(r)?;
+    for _ in 0..num_lipids {
+        let (id, species) = read_identity(r)?;
+        state.lipids.push(Lipid {
+            id,
+            species,
+            ..read_lipid(r)?
+        });
+    }
+    read_tags(r, &mut state)?;
+    for _ in 0..read_u32(r)? {
+        let id = read_u32(r)?;
+        let restraint = match read_u8(r)? {
+            0 => Restraint::Frozen,
+            1 => Restraint::Harmonic {
+                head: read_point(r)?,
+                tail: read_point(r)?,
+                stiffness: read_f32(r)?,
+            },
+            _ => return Err(invalid_data("bad restraint kind")),
+        };
+        state.restraints.insert(id, restraint);
+    }
+    Ok(state)
+}
+
+/// The container & obstacles
+pub(crate) fn write_walls(w: &mut impl Write, container: Option<&Shape>, obstacles: &[Obstacle]) -> io::Result<()> {
+    w.write_all(&[container.is_some() as u8])?;
+    if let Some(container) = container {
+        write_shape(w, container)?;
+    }
+    write_u32(w, obstacles.len() as u32)?;
+    for obstacle in obstacles {
+        write_obstacle(w, obstacle)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_walls(r: &mut impl Read) -> io::Result<(Option<Shape>, Vec<Obstacle>)> {
+    let container = if read_u8(r)? != 0 {
+        let kind = read_u8(r)?;
+        Some(read_shape(r, kind)?)
+    } else {
+        None
+    };
+    let obstacles = (0..read_u32(r)?).map(|_| read_obstacle(r)).collect::<io::Result<_>>()?;
+    Ok((container, obstacles))
+}
+
+fn write_obstacle(w: &mut impl Write, obstacle: &Obstacle) -> io::Result<()> {
+    let surface = match obstacle.surface {
+        Surface::Hydrophilic => 0,
+        Surface::Hydrophobic => 1,
+    };
+    w.write_all(&[surface])?;
+    write_shape(w, &obstacle.shape)
+}
+
+fn read_obstacle(r: &mut impl Read) -> io::Result<Obstacle> {
+    let surface = match read_u8(r)? {
+        0 => Surface::Hydrophilic,
+        1 => Surface::Hydrophobic,
+        _ => return Err(invalid_data("bad obstacle surface")),
+    };
+    let kind = read_u8(r)?;
+    Ok(Obstacle {
+        shape: read_shape(r, kind)?,
+        surface,
+    })
+}
+
+fn write_fields(w: &mut impl Write, fields: &Fields) -> io::Result<()> {
+    write_f32(w, fields.gravity.x)?;
+    write_f32(w, fields.gravity.y)?;
+    write_f32(w, fields.electric_field.x)?;
+    write_f32(w, fields.electric_field.y)?;
+    write_f32(w, fields.shear)?;
+    write_u32(w, fields.charges.len() as u32)?;
+    for charge in fields.charges.iter() {
+        write_f32(w, *charge)?;
+    }
+    w.write_all(&[fields.pull.is_some() as u8])?;
+    if let Some(pull) = &fields.pull {
+        write_u32(w, pull.lipids.len() as u32)?;
+        for id in pull.lipids.iter() {
+            write_u32(w, *id)?;
+        }
+        write_point(w, pull.anchor)?;
+        write_f32(w, pull.velocity.x)?;
+        write_f32(w, pull.velocity.y)?;
+        write_f32(w, pull.stiffness)?;
+    }
+    Ok(())
+}
+
+fn read_fields(r: &mut impl Read) -> io::Result<Fields> {
+    Ok(Fields {
+        gravity: Vector::new(read_f32(r)?, read_f32(r)?),
+        electric_field: Vector::new(read_f32(r)?, read_f32(r)?),
+        shear: read_f32(r)?,
+        charges: (0..read_u32(r)?).map(|_| read_f32(r)).collect::<io::Result<_>>()?,
+        pull: if read_u8(r)? != 0 {
+            Some(Pull {
+                lipids: (0..read_u32(r)?).map(|_| read_u32(r)).collect::<io::Result<_>>()?,
+                anchor: read_point(r)?,
+                velocity: Vector::new(read_f32(r)?, read_f32(r)?),
+                stiffness: read_f32(r)?,
+            })
+        } else {
+            None
+        },
+    })
+}
+
+fn write_shape(w: &mut impl Write, shape: &Shape) -> io::Result<()> {
+    let (kind, points, radius) = match shape {
+        Shape::Segment(a, b) => (0, vec![*a, *b], None),
+        Shape::Circle { centre, radius } => (1, vec![*centre], Some(*radius)),
+        Shape::Polygon(corners) => (2, corners.clone(), None),
+    };
+    w.write_all(&[kind])?;
+    write_u32(w, points.len() as u32)?;
+    for p in points {
+        write_point(w, p)?;
+    }
+    match radius {
+        Some(radius) => write_f32(w, radius),
+        None => Ok(()),
+    }
+}
+
+/// The rest of a shape, once its kind has been read
+fn read_shape(r: &mut impl Read, kind: u8) -> io::Result<Shape> {
+    let points = (0..read_u32(r)?).map(|_| read_point(r)).collect::<io::Result<Vec<_>>>()?;
+    match (kind, points.as_slice()) {
+        (0, [a, b]) => Ok(Shape::Segment(*a, *b)),
+        (1, [centre]) => Ok(Shape::Circle {
+            centre: *centre,
+            radius: read_f32(r)?,
+        }),
+        (2, [_, _, _, ..]) => Ok(Shape::Polygon(points)),
+        _ => Err(invalid_data("bad shape")),
+    }
+}
+
+/// Id & species
+pub(crate) fn write_identity(w: &mut impl Write, l: &Lipid) -> io::Result<()> {
+    write_u32(w, l.id)?;
+    write_u16(w, l.species)
+}
+
+pub(crate) fn read_identity(r: &mut impl Read) -> io::Result<(LipidId, u16)> {
+    Ok((read_u32(r)?, read_u16(r)?))
+}
+
+pub(crate) fn write_tags(w: &mut impl Write, state: &State) -> io::Result<()> {
+    write_u32(w, state.tags.len() as u32)?;
+    for (id, tag) in state.tags.iter() {
+        write_u32(w, *id)?;
+        write_string(w, tag)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_tags(r: &mut impl Read, state: &mut State) -> io::Result<()> {
+    let num_tags = read_u32(r)?;
+    for _ in 0..num_tags {
+        let id = read_u32(r)?;
+        state.tags.insert(id, read_string(r)?);
+    }
+    Ok(())
+}
+
+/// Everything but the id & species, which are left 0
+pub(crate) fn write_lipid(w: &mut impl Write, l: &Lipid) -> io::Result<()> {
+    write_point(w, l.head_position)?;
+    write_point(w, l.tail_position)?;
+    write_f32(w, l.linear_velocity.x)?;
+    write_f32(w, l.linear_velocity.y)?;
+    write_f32(w, l.angular_velocity)?;
+    write_f32(w, l.head_radius)?;
+    write_f32(w, l.tail_length)?;
+    write_f32(w, l.tail_width)
+}
+
+pub(crate) fn read_lipid(r: &mut impl Read) -> io::Result<Lipid> {
+    Ok(Lipid {
+        id: 0,
+        species: 0,
+        head_position: read_point(r)?,
+        tail_position: read_point(r)?,
+        linear_velocity: Vector::new(read_f32(r)?, read_f32(r)?),
+        angular_velocity: read_f32(r)?,
+        head_radius: read_f32(r)?,
+        tail_length: read_f32(r)?,
+        tail_width: read_f32(r)?,
+    })
+}
+
+pub(crate) fn write_point(w: &mut impl Write, p: Point) -> io::Result<()> {
+    write_f32(w, p.x)?;
+    write_f32(w, p.y)
+}
+
+pub(crate) fn read_point(r: &mut impl Read) -> io::Result<Point> {
+    Ok(Point::new(read_f32(r)?, read_f32(r)?))
+}
+
+pub(crate) fn write_f32(w: &mut impl Write, v: f32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_f32(r: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+pub(crate) fn write_f64(w: &mut impl Write, v: f64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0u8];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub(crate) fn write_u16(w: &mut impl Write, v: u16) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    r.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub(crate) fn write_u32(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn write_u64(w: &mut impl Write, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// u32 length, then UTF-8
+pub(crate) fn write_string(w: &mut impl Write, s: &str) -> io::Result<()> {
+    write_u32(w, s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+pub(crate) fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0u8; read_u32(r)? as usize];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("string isn't UTF-8"))
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
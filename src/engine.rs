@@ -1,45 +1,569 @@
+use crate::checkpoint::{self, Checkpoint, Snapshot};
+use crate::geometry::{Obstacle, Shape, Surface};
 use crate::types::*;
 use cgmath::Basis2;
 use cgmath::Rad;
 use cgmath::Rotation;
 use cgmath::Rotation2;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 use std::time::Instant;
 
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 
+const CENTER_FRAC: f32 = 0.33; // fraction for the distance from head to the center of mass
+const FIRST_MOMENT: f32 = 30.0;
+const FRICTION_LOSS_FRAC: f32 = 0.995;
+const MIN_ERROR2: f32 = 0.5 * 0.5;
+/// Lower and upper corners of the box lipids are kept in: the water grid, less a margin.
+pub const DEFAULT_BOUNDS: (Point, Point) = (Point { x: 3.0, y: 3.0 }, Point { x: 397.0, y: 397.0 });
+/// Water grid cells past the upper corner of the bounds, so the kernels fit around any point in bounds. The lower corner
+/// needs as many before it.
+const GRID_MARGIN: f32 = 3.0;
+/// Most the barostat stretches or squeezes the box by in one tick
+const MAX_BOX_SCALE: f32 = 0.001;
+/// How far from an obstacle's surface it wets (or dries) the water grid, in cells: the reach of the water kernels
+const OBSTACLE_WATER_RANGE: f32 = 2.0;
+/// Force per unit of overlap pushing heads & tails out of obstacles
+const OBSTACLE_STIFFNESS: f32 = 50.0;
+pub const MAX_DIST2: f32 = 11.0 * 11.0; // try to make the forces only short-ranged, like surface tension is
+pub const TAIL_POINTS: [f32; 3] = [0.33, 0.67, 1.0]; // multi-point attraction & repulsion from/to tails
+
+/// How the engine picks the time step for each tick.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimeStep {
+    /// Always use this step (unless the stability guard has to halve it).
+    Fixed(f32),
+    /// Pick the largest step (clamped to `min..=max`) for which no lipid is expected to move further than
+    /// `max_displacement`, judging by its current velocity and the forces acting on it.
+    Adaptive { max_displacement: f32, min: f32, max: f32 },
+}
+
+/// Constant pressure, Berendsen-style: after each tick, the box is stretched along `axes` by a factor of
+/// `1 + coupling * time_step * (pressure - target)` (see `State::pressure`), about its lower corner, and the lipids with
+/// it. Obstacles and the container stay where they are; if any lipid couldn't follow, the box is left as it is that tick.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Barostat {
+    pub target: f32,
+    pub coupling: f32,
+    pub axes: BoxAxes,
+}
+
+impl Default for Barostat {
+    fn default() -> Self {
+        Self {
+            target: 0.1,
+            coupling: 100.0,
+            axes: BoxAxes::Both,
+        }
+    }
+}
+
+/// Which sides of the box the barostat changes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BoxAxes {
+    /// Both by the same factor, following the mean of the two pressures
+    Both,
+    /// Only the width, following the pressure along x: the lateral pressure of a membrane lying along x
+    X,
+    /// Only the height, following the pressure along y
+    Y,
+}
+
+impl FromStr for BoxAxes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "xy" => Ok(BoxAxes::Both),
+            "x" => Ok(BoxAxes::X),
+            "y" => Ok(BoxAxes::Y),
+            _ => Err(format!("unknown axes {s:?}, try xy, x or y")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub time_step: TimeStep,
+    /// If any head or tail moves further than this in one tick, the tick is rolled back and redone with half the step.
+    pub max_jump: f32,
+    /// The stability guard gives up halving below this step, and accepts whatever that step produces.
+    pub min_time_step: f32,
+    /// A lipid whose head and tail are further apart than this multiple of its `tail_length` is considered broken.
+    pub max_bond_stretch: f32,
+    /// For the random perturbations
+    pub seed: u64,
+    /// Resizes the box to keep the pressure at a target; the bounds stay as they are without one.
+    pub barostat: Option<Barostat>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            time_step: TimeStep::Fixed(0.0001),
+            max_jump: 2.0,
+            min_time_step: 1e-7,
+            max_bond_stretch: 2.0,
+            seed: 1,
+            barostat: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Time steps have to be positive & finite, and an adaptive one's range not empty (`tick` clamps to it).
+    pub fn check(&self) -> Result<(), String> {
+        let positive = |name: &str, value: f32| {
+            if value > 0.0 && value.is_finite() {
+                Ok(())
+            } else {
+                Err(format!("{name} has to be positive & finite, not {value}"))
+            }
+        };
+        match self.time_step {
+            TimeStep::Fixed(time_step) => positive("time step", time_step)?,
+            TimeStep::Adaptive {
+                max_displacement,
+                min,
+                max,
+            } => {
+                positive("max displacement", max_displacement)?;
+                positive("min time step", min)?;
+                positive("max time step", max)?;
+                if min > max {
+                    return Err(format!("min time step {min} is above the max {max}"));
+                }
+            }
+        }
+        positive("stability guard's min time step", self.min_time_step)
+    }
+}
+
+/// Forces from outside the system: fields that are the same at every tick, and a pull that isn't.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fields {
+    /// Body force on every lipid, at its centre of mass
+    pub gravity: Vector,
+    /// Pulls on each head in proportion to its charge
+    pub electric_field: Vector,
+    /// Charge on the heads of each species, in order of species; species past the end are neutral.
+    pub charges: Vec<f32>,
+    /// Force along x on each head & tail point per unit of height above the middle of the bounds. Against the friction,
+    /// that makes lipids drift at a speed proportional to their height, as in a linear shear flow, and turn like rods in
+    /// one.
+    pub shear: f32,
+    pub pull: Option<Pull>,
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self {
+            gravity: Vector::new(0.0, 0.0),
+            electric_field: Vector::new(0.0, 0.0),
+            charges: vec![],
+            shear: 0.0,
+            pull: None,
+        }
+    }
+}
+
+/// Steered dynamics: a spring from the centre of a group of lipids to an anchor moving at constant velocity. The spring
+/// force is shared evenly by the lipids, at their centres of mass, so it drags the group along without turning it; how
+/// hard it has to pull to keep up is what gets measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pull {
+    /// Lipids that have gone are left out, and the pull stops once none are left.
+    pub lipids: Vec<LipidId>,
+    /// Where the anchor is at time 0
+    pub anchor: Point,
+    pub velocity: Vector,
+    /// Force per unit of distance between the anchor and the group's centre
+    pub stiffness: f32,
+}
+
+/// The spring of a `Pull` at one moment
+#[derive(Debug, Copy, Clone)]
+pub struct PullReading {
+    pub anchor: Point,
+    /// Mean centre of mass of the lipids pulled
+    pub centre: Point,
+    /// Total force on the group, towards the anchor
+    pub force: Vector,
+    /// How many lipids share it
+    pub lipids: usize,
+}
+
+impl Pull {
+    pub fn anchor_at(&self, time: f64) -> Point {
+        self.anchor + self.velocity * time as f32
+    }
+
+    /// `None` if none of the lipids are in `state`
+    pub fn reading(&self, state: &State) -> Option<PullReading> {
+        let pulled: Vec<&Lipid> = state.lipids.iter().filter(|l| self.lipids.contains(&l.id)).collect();
+        if pulled.is_empty() {
+            return None;
+        }
+        let sum = pulled
+            .iter()
+            .map(|l| l.tail_point(CENTER_FRAC) - Point::new(0.0, 0.0))
+            .fold(Vector::new(0.0, 0.0), |a, b| a + b);
+        let centre = Point::new(0.0, 0.0) + sum / pulled.len() as f32;
+        let anchor = self.anchor_at(state.time);
+        Some(PullReading {
+            anchor,
+            centre,
+            force: (anchor - centre) * self.stiffness,
+            lipids: pulled.len(),
+        })
+    }
+}
+
+/// The part of a tick that was being evaluated when a problem was found.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Term {
+    /// The state as it was handed to the tick
+    Input,
+    HeadWater,
+    TailWater,
+    /// Attraction & repulsion between lipids
+    Pairs,
+    /// Lipids being pushed out of obstacles
+    Obstacles,
+    /// Gravity, electric field, shear & pulling
+    Fields,
+    /// Harmonic restraints pulling lipids back to where they were
+    Restraints,
+    Noise,
+    /// Applying the forces to velocities & positions
+    Integration,
+}
+
+/// Lipids are named by id, which stays the same whatever happens to the others.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimError {
+    /// A force, torque, position or velocity stopped being a number.
+    NonFinite { lipid: LipidId, term: Term, value: f32 },
+    /// A lipid's head and tail drifted much further apart than its `tail_length`.
+    BondExplosion { lipid: LipidId, length: f32, tail_length: f32 },
+    /// A head or tail left the simulation bounds (and the water grid).
+    OutOfDomain { lipid: LipidId, term: Term, position: Point },
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimError::NonFinite { lipid, term, value } => write!(f, "lipid {lipid}: non-finite value {value} in {term:?}"),
+            SimError::BondExplosion {
+                lipid,
+                length,
+                tail_length,
+            } => write!(f, "lipid {lipid}: head & tail are {length} apart, but tail_length is {tail_length}"),
+            SimError::OutOfDomain { lipid, term, position } => {
+                write!(
+                    f,
+                    "lipid {lipid}: position ({}, {}) out of bounds in {term:?}",
+                    position.x, position.y
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+/// How `Engine::minimize` went
+#[derive(Debug, Copy, Clone)]
+pub struct Minimization {
+    pub iterations: usize,
+    /// Largest force left on any lipid, counting torques as the force they'd make at the end of the tail
+    pub max_force: f32,
+    pub converged: bool,
+}
+
+/// Which forces `compute_forces` adds up
+#[derive(Copy, Clone)]
+enum Forces<'a> {
+    /// Everything a tick uses
+    All { water: &'a ::ndarray::Array2<f64> },
+    /// Only those that keep lipids from overlapping each other & the obstacles
+    Steric,
+}
+
+/// Force & torque (CCW is positive) acting on one lipid, about its centre of mass.
+#[derive(Debug, Copy, Clone)]
+struct ExtForce {
+    force: Vector,
+    torque: f32,
+}
+
 pub struct Engine {
     prev: State,
     curr: State,
     rng: SmallRng,
     bounds: (Point, Point),
+    /// Lipids are kept inside this as well as the bounds
+    container: Option<Shape>,
+    obstacles: Vec<Obstacle>,
+    /// What the obstacles add to the water grid, which never changes
+    obstacle_water: ::ndarray::Array2<f64>,
+    fields: Fields,
+    settings: Settings,
 }
 
 impl Engine {
-    pub fn new(initial_state: State) -> Self {
+    pub fn new(initial_state: State, settings: Settings) -> Self {
         Self {
             prev: initial_state.clone(),
             curr: initial_state,
-            rng: SmallRng::from_seed([
-                1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
-            ]),
-            bounds: ((3., 3.).into(), (397., 397.).into()),
+            rng: SmallRng::seed_from_u64(settings.seed),
+            bounds: DEFAULT_BOUNDS,
+            container: None,
+            obstacles: vec![],
+            obstacle_water: obstacle_water(&[], grid_shape(DEFAULT_BOUNDS)),
+            fields: Fields::default(),
+            settings,
         }
     }
 
-    pub fn tick(&mut self) {
+    /// A circle or polygon to keep the lipids in, on top of the bounds. Lipids already outside it are left there, and
+    /// can get in but not back out.
+    pub fn set_container(&mut self, container: Option<Shape>) {
+        self.container = container;
+    }
+
+    pub fn container(&self) -> Option<&Shape> {
+        self.container.as_ref()
+    }
+
+    pub fn set_fields(&mut self, fields: Fields) {
+        self.fields = fields;
+    }
+
+    pub fn fields(&self) -> &Fields {
+        &self.fields
+    }
+
+    /// Replaces the obstacles. Lipids already inside one are left there; they get pushed out over the next ticks.
+    pub fn set_obstacles(&mut self, obstacles: Vec<Obstacle>) {
+        self.obstacle_water = obstacle_water(&obstacles, grid_shape(self.bounds));
+        self.obstacles = obstacles;
+    }
+
+    pub fn obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+
+    /// Advances the simulation by one step. On error, the offending state is kept as the current one.
+    pub fn tick(&mut self) -> Result<(), SimError> {
+        self.check_state(Term::Input)?;
         self.prev = self.curr.clone();
         let start_time = Instant::now();
-        let time_step = 0.0001 as f32;
-        let center_frac = 0.33 as f32; // fraction for the distance from head to the center of mass
-        let first_moment = 30.0 as f32;
-        let friction_loss_frac = 0.995;
-        let min_error2: f32 = (0.5 as f32).powf(2.0);
-        let max_dist2: f32 = (11.0 as f32).powf(2.0); // try to make the forces only short-ranged, like surface tension is
-        let tail_points = [0.33, 0.67, 1.0]; // multi-point attraction & repulsion from/to tails
-        let num_tail_points_f = tail_points.len() as f32;
-
-        let mut water = ::ndarray::Array2::<f64>::zeros((400, 400));
+
+        let water = self.compute_water();
+        let (forces, virial) = self.compute_forces(Forces::All { water: &water })?;
+        let kinetic = self.prev.lipids.iter().fold(Vector::new(0.0, 0.0), |sum, l| {
+            sum + Vector::new(l.linear_velocity.x.powi(2), l.linear_velocity.y.powi(2))
+        });
+        let size = self.bounds.1 - self.bounds.0;
+        self.curr.pressure = (kinetic + virial) / (size.x * size.y);
+
+        let mut time_step = match self.settings.time_step {
+            TimeStep::Fixed(time_step) => time_step,
+            TimeStep::Adaptive {
+                max_displacement,
+                min,
+                max,
+            } => self.adaptive_time_step(&forces, max_displacement).clamp(min, max),
+        };
+
+        // stability guard: if something jumped too far, go back to where we were and try again with a smaller step
+        loop {
+            self.integrate(&forces, time_step);
+            if time_step * 0.5 < self.settings.min_time_step || self.max_jump() <= self.settings.max_jump {
+                break;
+            }
+            self.curr.lipids.clone_from(&self.prev.lipids);
+            time_step *= 0.5;
+        }
+        self.check_state(Term::Integration)?;
+        if let Some(barostat) = self.settings.barostat {
+            self.rescale(barostat, time_step);
+        }
+
+        self.curr.tick += 1;
+        self.curr.time += time_step as f64;
+        self.curr.time_step = time_step;
+        self.curr.tick_time = Instant::now() - start_time;
+        Ok(())
+    }
+
+    /// Relaxes overlaps before dynamics start, by steepest descent on the pair & obstacle forces `tick` uses: each
+    /// iteration moves every lipid as a rigid body along its force and turns it with its torque, scaled so the lipid
+    /// pushed hardest moves `max_step`. The water, the fields, the restraints and the noise are left out: the water
+    /// forces aren't the gradient of any energy (they even push on a lipid on its own), so there's nothing for them to
+    /// converge to, while the pair & obstacle forces only ever push overlapping things apart. Moves that would leave the
+    /// bounds or the container, or go into an obstacle, are skipped, and frozen lipids stay put. Stops once the largest
+    /// force (or torque over tail length) is at most `tolerance`, or after `max_iterations`. Velocities are zeroed; the
+    /// tick count and time are untouched, and no random numbers are drawn.
+    pub fn minimize(&mut self, tolerance: f32, max_iterations: usize, max_step: f32) -> Result<Minimization, SimError> {
+        self.check_state(Term::Input)?;
+        let mut forces = self.relaxation_forces()?;
+        let mut max_force = max_generalized_force(&self.curr, &forces);
+        let mut iterations = 0;
+        while max_force > tolerance && iterations < max_iterations {
+            iterations += 1;
+            let scale = max_step / max_force;
+            let walls = Walls::of(self.bounds, self.container.as_ref(), &self.obstacles);
+            for (l, f) in self.curr.lipids.iter_mut().zip(forces.iter()) {
+                if let Some(Restraint::Frozen) = self.curr.restraints.get(&l.id) {
+                    continue;
+                }
+                let centre_of_mass = l.head_position + (l.tail_position - l.head_position) * CENTER_FRAC;
+                let rotation: Basis2<f32> = Rotation2::from_angle(Rad(scale * f.torque / l.tail_length.powi(2)));
+                let shift = f.force * scale;
+                let head = centre_of_mass + shift + rotation.rotate_vector(l.head_position - centre_of_mass);
+                let tail = centre_of_mass + shift + rotation.rotate_vector(l.tail_position - centre_of_mass);
+                if walls.allow(l.head_position, head) && walls.allow(l.tail_position, tail) {
+                    l.head_position = head;
+                    l.tail_position = tail;
+                }
+            }
+            self.check_state(Term::Integration)?;
+            forces = self.relaxation_forces()?;
+            max_force = max_generalized_force(&self.curr, &forces);
+        }
+
+        for l in self.curr.lipids.iter_mut() {
+            l.linear_velocity = Vector::new(0.0, 0.0);
+            l.angular_velocity = 0.0;
+        }
+        Ok(Minimization {
+            iterations,
+            max_force,
+            converged: max_force <= tolerance,
+        })
+    }
+
+    /// The pair & obstacle forces on `curr`
+    fn relaxation_forces(&mut self) -> Result<Vec<ExtForce>, SimError> {
+        self.prev = self.curr.clone();
+        Ok(self.compute_forces(Forces::Steric)?.0)
+    }
+
+    pub fn current_state(&self) -> State {
+        self.curr.clone()
+    }
+
+    pub fn state(&self) -> &State {
+        &self.curr
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.curr.tick
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Lower and upper corners of the box the lipids are kept in. Only the barostat changes them.
+    pub fn bounds(&self) -> (Point, Point) {
+        self.bounds
+    }
+
+    /// Resumes a run saved by `write_checkpoint`, with the settings it was saved with, so it continues bit-for-bit like
+    /// the original.
+    pub fn from_checkpoint(r: &mut impl Read) -> io::Result<Self> {
+        let Checkpoint {
+            seed,
+            settings,
+            bounds,
+            container,
+            obstacles,
+            fields,
+            prev,
+            curr,
+        } = checkpoint::read(r)?;
+        Ok(Self {
+            prev,
+            curr,
+            rng: SmallRng::from_seed(seed),
+            bounds,
+            container,
+            obstacle_water: obstacle_water(&obstacles, grid_shape(bounds)),
+            obstacles,
+            fields,
+            settings,
+        })
+    }
+
+    /// `SmallRng` can't be saved as-is, so this reseeds the engine from its own RNG and saves the new seed instead.
+    /// That way, the run that wrote the checkpoint and every run resumed from it draw the same numbers from here on.
+    pub fn write_checkpoint(&mut self, w: &mut impl Write) -> io::Result<()> {
+        let seed: [u8; 32] = self.rng.r#gen();
+        self.rng = SmallRng::from_seed(seed);
+        checkpoint::write(
+            w,
+            &Snapshot {
+                seed: &seed,
+                settings: &self.settings,
+                bounds: self.bounds,
+                container: self.container.as_ref(),
+                obstacles: &self.obstacles,
+                fields: &self.fields,
+                prev: &self.prev,
+                curr: &self.curr,
+            },
+        )
+    }
+
+    /// Makes sure every lipid of `curr` is made of numbers, in one piece, and inside the bounds (so it can't index
+    /// outside the water grid).
+    fn check_state(&self, term: Term) -> Result<(), SimError> {
+        for l in self.curr.lipids.iter() {
+            let values = [
+                l.head_position.x,
+                l.head_position.y,
+                l.tail_position.x,
+                l.tail_position.y,
+                l.linear_velocity.x,
+                l.linear_velocity.y,
+                l.angular_velocity,
+            ];
+            if let Some(value) = values.into_iter().find(|v| !v.is_finite()) {
+                return Err(SimError::NonFinite { lipid: l.id, term, value });
+            }
+
+            for position in [l.head_position, l.tail_position] {
+                if !in_bounds(self.bounds, position) {
+                    return Err(SimError::OutOfDomain {
+                        lipid: l.id,
+                        term,
+                        position,
+                    });
+                }
+            }
+
+            let length = l.head_position.distance(l.tail_position);
+            if length > l.tail_length * self.settings.max_bond_stretch {
+                return Err(SimError::BondExplosion {
+                    lipid: l.id,
+                    length,
+                    tail_length: l.tail_length,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Accumulates heads (+) and tails (-) onto a grid, and mirrors it into the state & the debug display.
+    fn compute_water(&mut self) -> ::ndarray::Array2<f64> {
+        let (rows, columns) = grid_shape(self.bounds);
+        let mut water = ::ndarray::Array2::<f64>::zeros((rows, columns));
         let water_kernel = ::ndarray::arr2(&[
             [0.0, 0.5, 1.0, 0.5, 0.0],
             [0.5, 1.0, 1.0, 1.0, 0.0],
@@ -47,7 +571,7 @@ impl Engine {
             [0.0, 1.0, 1.0, 1.0, 0.0],
             [0.0, 0.5, 1.0, 0.5, 0.0],
         ]);
-        for l in self.curr.lipids.iter() {
+        for l in self.prev.lipids.iter() {
             let head_index_x = l.head_position.x as usize;
             let head_index_y = l.head_position.y as usize;
             let mut local_water = water.slice_mut(::ndarray::s![
@@ -56,7 +580,7 @@ impl Engine {
             ]);
             local_water += &water_kernel;
 
-            for tail_distance1 in tail_points.iter() {
+            for tail_distance1 in TAIL_POINTS.iter() {
                 let tail_ipos = l.head_position + (l.tail_position - l.head_position) * *tail_distance1;
                 let tail_index_ix = tail_ipos.x as usize;
                 let tail_index_iy = tail_ipos.y as usize;
@@ -69,10 +593,16 @@ impl Engine {
             }
         }
 
+        if !self.obstacles.is_empty() {
+            water += &self.obstacle_water;
+        }
         for w in water.iter_mut() {
-            *w = w.min(1.0).max(-1.0);
+            *w = w.clamp(-1.0, 1.0);
         }
 
+        if self.curr.debug_array0.dim() != (rows, columns, 4) {
+            self.curr.debug_array0 = ::ndarray::Array3::zeros((rows, columns, 4));
+        }
         self.curr
             .debug_array0
             .index_axis_mut(::ndarray::Axis(2), 2) // blue channel
@@ -80,8 +610,21 @@ impl Engine {
         self.curr
             .debug_array0
             .index_axis_mut(::ndarray::Axis(2), 0) // red channel
-            .assign(&water.mapv(|e| ((e * -1.0).max(0.0) * 255.0) as u8));
+            .assign(&water.mapv(|e| ((-e).max(0.0) * 255.0) as u8));
         self.curr.debug_array0.index_axis_mut(::ndarray::Axis(2), 3).fill(255); // alpha
+        self.curr.water = water.mapv(|e| e as f32);
+
+        water
+    }
+
+    /// What acts on each lipid of `prev`: water, pair interactions with the other lipids, obstacles, fields and noise, or
+    /// just the pairs & obstacles. Also the virial of the pair interactions, along x & y.
+    fn compute_forces(&mut self, which: Forces) -> Result<(Vec<ExtForce>, Vector), SimError> {
+        let water = match which {
+            Forces::All { water } => Some(water),
+            Forces::Steric => None,
+        };
+        let num_tail_points_f = TAIL_POINTS.len() as f32;
 
         // make head longer?
         // simulate water. bilayers do not form without water IRL
@@ -96,14 +639,24 @@ impl Engine {
             [0.1, 0.3, 0.7, 0.3, 0.1],
             [0.0, 0.1, 0.3, 0.1, 0.0],
         ]);
-        let y_kernel = x_kernel.t().clone();
+        let y_kernel = x_kernel.t();
 
-        for (ilipid, l) in self.curr.lipids.iter_mut().enumerate() {
+        // share of the pulling force each lipid pulled gets
+        let pull_share = match which {
+            Forces::All { .. } => self.fields.pull.as_ref().and_then(|pull| pull.reading(&self.prev)),
+            Forces::Steric => None,
+        }
+        .map(|reading| reading.force / reading.lipids as f32);
+
+        // along x & y, of the forces between lipids
+        let mut virial = Vector::new(0.0, 0.0);
+        let mut forces = Vec::with_capacity(self.prev.lipids.len());
+        for (ilipid, l) in self.prev.lipids.iter().enumerate() {
             let mut ext_force = Vector { x: 0., y: 0. };
-            let centre_of_mass = l.head_position + (l.tail_position - l.head_position) * center_frac;
+            let centre_of_mass = l.head_position + (l.tail_position - l.head_position) * CENTER_FRAC;
             let mut ext_torque: f32 = 0.0; // (CCW is positive)
 
-            {
+            if let Some(water) = water {
                 // water: head
                 let head_index_x = l.head_position.x as usize;
                 let head_index_y = l.head_position.y as usize;
@@ -120,27 +673,31 @@ impl Engine {
                 ext_force += force_here;
                 ext_torque += offset.x * force_here.y - offset.y * force_here.x;
             }
+            check_force(l.id, Term::HeadWater, ext_force, ext_torque)?;
 
-            // water: tail
-            for tail_distance1 in tail_points.iter() {
-                let tail_ipos = l.head_position + (l.tail_position - l.head_position) * *tail_distance1;
-                let tail_index_ix = tail_ipos.x as usize;
-                let tail_index_iy = tail_ipos.y as usize;
+            if let Some(water) = water {
+                // water: tail
+                for tail_distance1 in TAIL_POINTS.iter() {
+                    let tail_ipos = l.head_position + (l.tail_position - l.head_position) * *tail_distance1;
+                    let tail_index_ix = tail_ipos.x as usize;
+                    let tail_index_iy = tail_ipos.y as usize;
 
-                let local_water = water.slice(::ndarray::s![
-                    tail_index_iy - 2..tail_index_iy + 3,
-                    tail_index_ix - 2..tail_index_ix + 3
-                ]);
-                let force_here = 1000.0 / num_tail_points_f
-                    * Vector {
-                        x: -x_kernel.iter().zip(local_water.iter()).map(|(x, y)| x * y).sum::<f64>() as f32,
-                        y: -y_kernel.iter().zip(local_water.iter()).map(|(x, y)| x * y).sum::<f64>() as f32,
-                    };
+                    let local_water = water.slice(::ndarray::s![
+                        tail_index_iy - 2..tail_index_iy + 3,
+                        tail_index_ix - 2..tail_index_ix + 3
+                    ]);
+                    let force_here = 1000.0 / num_tail_points_f
+                        * Vector {
+                            x: -x_kernel.iter().zip(local_water.iter()).map(|(x, y)| x * y).sum::<f64>() as f32,
+                            y: -y_kernel.iter().zip(local_water.iter()).map(|(x, y)| x * y).sum::<f64>() as f32,
+                        };
 
-                let offset = centre_of_mass - tail_ipos;
-                ext_force += force_here;
-                ext_torque += offset.x * force_here.y - offset.y * force_here.x;
+                    let offset = centre_of_mass - tail_ipos;
+                    ext_force += force_here;
+                    ext_torque += offset.x * force_here.y - offset.y * force_here.x;
+                }
             }
+            check_force(l.id, Term::TailWater, ext_force, ext_torque)?;
 
             for (jlipid, jl) in self.prev.lipids.iter().enumerate() {
                 if jlipid == ilipid {
@@ -150,41 +707,44 @@ impl Engine {
                 // attraction & repulsion on our head from the other head
                 let head_dist2 = jl.head_position.distance2(l.head_position);
                 let head_error2 = head_dist2 - (l.head_radius + jl.head_radius).powf(2.0);
-                if min_error2 < head_error2.abs() && head_dist2 < max_dist2 {
+                if MIN_ERROR2 < head_error2.abs() && head_dist2 < MAX_DIST2 {
                     let coeff = if head_error2 < 0.0 { -1.5 } else { 0.0 };
                     let force_here = coeff * (jl.head_position - l.head_position);
                     let offset = centre_of_mass - l.head_position;
                     ext_force += force_here;
+                    virial += half_virial(l.head_position, jl.head_position, force_here);
                     ext_torque += offset.x * force_here.y - offset.y * force_here.x;
                 }
 
                 // repulsion on this head from the other tail points
-                for tail_distance2 in tail_points.iter() {
+                for tail_distance2 in TAIL_POINTS.iter() {
                     let tpos_j = jl.head_position + (jl.tail_position - jl.head_position) * *tail_distance2;
                     let tail_tail_dist2 = tpos_j.distance2(l.head_position);
                     let tail_tail_error2 = tail_tail_dist2 - (l.head_radius + jl.tail_width / 2.0).powf(2.0);
-                    if min_error2 < tail_tail_error2.abs() && tail_tail_dist2 < max_dist2 {
+                    if MIN_ERROR2 < tail_tail_error2.abs() && tail_tail_dist2 < MAX_DIST2 {
                         let coeff = if tail_tail_error2 < 0.0 { -1.5 } else { 0.0 };
                         let force_here = coeff / num_tail_points_f * (tpos_j - l.head_position);
                         let offset = centre_of_mass - l.head_position;
                         ext_force += force_here;
+                        virial += half_virial(l.head_position, tpos_j, force_here);
                         ext_torque += offset.x * force_here.y - offset.y * force_here.x;
                     }
                 }
 
-                for tail_distance1 in tail_points.iter() {
+                for tail_distance1 in TAIL_POINTS.iter() {
                     let tpos_i = l.head_position + (l.tail_position - l.head_position) * *tail_distance1;
 
                     // atraction and repulsion on this tail point from the other tail points
-                    for tail_distance2 in tail_points.iter() {
+                    for tail_distance2 in TAIL_POINTS.iter() {
                         let tpos_j = jl.head_position + (jl.tail_position - jl.head_position) * *tail_distance2;
                         let tail_tail_dist2 = tpos_j.distance2(tpos_i);
                         let tail_tail_error2 = tail_tail_dist2 - (l.tail_width / 2.0 + jl.tail_width / 2.0).powf(2.0);
-                        if min_error2 < tail_tail_error2.abs() && tail_tail_dist2 < max_dist2 {
+                        if MIN_ERROR2 < tail_tail_error2.abs() && tail_tail_dist2 < MAX_DIST2 {
                             let coeff = if tail_tail_error2 < 0.0 { -1.5 } else { 0.0 };
                             let force_here = coeff / num_tail_points_f.powf(2.0) * (tpos_j - tpos_i);
                             let offset = centre_of_mass - tpos_i;
                             ext_force += force_here;
+                            virial += half_virial(tpos_i, tpos_j, force_here);
                             ext_torque += offset.x * force_here.y - offset.y * force_here.x;
                         }
                     }
@@ -192,32 +752,184 @@ impl Engine {
                     // repulsion on this tail point from the other head
                     let head_tail_dist2 = jl.head_position.distance2(tpos_i);
                     let head_tail_error2 = head_tail_dist2 - (l.tail_width / 2.0 + jl.head_radius).powf(2.0);
-                    if min_error2 < head_tail_error2.abs() && head_tail_dist2 < max_dist2 {
+                    if MIN_ERROR2 < head_tail_error2.abs() && head_tail_dist2 < MAX_DIST2 {
                         let coeff = if head_tail_error2 < 0.0 { -1.5 } else { 0.0 };
                         let force_here = coeff / num_tail_points_f * (jl.head_position - tpos_i);
                         let offset = centre_of_mass - tpos_i;
                         ext_force += force_here;
+                        virial += half_virial(tpos_i, jl.head_position, force_here);
                         ext_torque += offset.x + force_here.y - offset.y * force_here.x;
                     }
                 }
             }
 
-            // random (~brownian) perturbations
-            ext_force += Vector {
-                x: self.rng.gen_range(-1.0..1.0),
-                y: self.rng.gen_range(-1.0..1.0),
-            } * 20000.0;
-            ext_torque += self.rng.gen_range(-1.0..1.0) * 4000.0;
-
-            // pull/push the head and tail of the same lipid together/apart if they are too far from the natural distance
-            let head_tail_distance2 = l.head_position.distance2(l.tail_position);
-            let head_tail_error2 = head_tail_distance2 - l.tail_length.powf(2.0);
-            let head_tail_attraction = if head_tail_error2.abs() > 0.1 {
-                let tail_to_head_unit = (l.tail_position - l.head_position) * head_tail_error2;
-                tail_to_head_unit / head_tail_distance2
+            check_force(l.id, Term::Pairs, ext_force, ext_torque)?;
+
+            for obstacle in self.obstacles.iter() {
+                // contact: pushed straight out of the surface, harder the deeper in
+                let head = (l.head_position, l.head_radius, 1.0);
+                let tail_points = TAIL_POINTS
+                    .iter()
+                    .map(|frac| (l.tail_point(*frac), l.tail_width / 2.0, 1.0 / num_tail_points_f));
+                for (position, reach, weight) in std::iter::once(head).chain(tail_points) {
+                    let (closest, distance) = obstacle.shape.nearest(position);
+                    let outward = if distance < 0.0 { closest - position } else { position - closest };
+                    if distance >= reach || outward.magnitude2() == 0.0 {
+                        continue;
+                    }
+                    let force_here = outward.normalize() * (OBSTACLE_STIFFNESS * weight * (reach - distance));
+                    let offset = centre_of_mass - position;
+                    ext_force += force_here;
+                    ext_torque += offset.x * force_here.y - offset.y * force_here.x;
+                }
+            }
+            check_force(l.id, Term::Obstacles, ext_force, ext_torque)?;
+
+            if let Forces::All { .. } = which {
+                let fields = &self.fields;
+                // gravity acts at the centre of mass, so it can't turn anything
+                ext_force += fields.gravity;
+
+                let charge = fields.charges.get(l.species as usize).copied().unwrap_or(0.0);
+                let force_here = fields.electric_field * charge;
+                let offset = centre_of_mass - l.head_position;
+                ext_force += force_here;
+                ext_torque += offset.x * force_here.y - offset.y * force_here.x;
+
+                // shear: half on the head, half spread along the tail
+                let middle = (self.bounds.0.y + self.bounds.1.y) / 2.0;
+                let head = (l.head_position, 0.5);
+                let tail_points = TAIL_POINTS.iter().map(|frac| (l.tail_point(*frac), 0.5 / num_tail_points_f));
+                for (position, weight) in std::iter::once(head).chain(tail_points) {
+                    let force_here = Vector::new(fields.shear * weight * (position.y - middle), 0.0);
+                    let offset = centre_of_mass - position;
+                    ext_force += force_here;
+                    ext_torque += offset.x * force_here.y - offset.y * force_here.x;
+                }
+
+                if let (Some(share), Some(pull)) = (pull_share, &fields.pull)
+                    && pull.lipids.contains(&l.id)
+                {
+                    ext_force += share;
+                }
+                check_force(l.id, Term::Fields, ext_force, ext_torque)?;
+
+                if let Some(Restraint::Harmonic { head, tail, stiffness }) = self.prev.restraints.get(&l.id) {
+                    for (position, reference) in [(l.head_position, *head), (l.tail_position, *tail)] {
+                        let force_here = (reference - position) * *stiffness;
+                        let offset = centre_of_mass - position;
+                        ext_force += force_here;
+                        ext_torque += offset.x * force_here.y - offset.y * force_here.x;
+                    }
+                }
+                check_force(l.id, Term::Restraints, ext_force, ext_torque)?;
+            }
+
+            if let Forces::All { .. } = which {
+                // random (~brownian) perturbations
+                ext_force += Vector {
+                    x: self.rng.gen_range(-1.0..1.0),
+                    y: self.rng.gen_range(-1.0..1.0),
+                } * 20000.0;
+                ext_torque += self.rng.gen_range(-1.0..1.0) * 4000.0;
+                check_force(l.id, Term::Noise, ext_force, ext_torque)?;
+            }
+
+            forces.push(ExtForce {
+                force: ext_force,
+                torque: ext_torque,
+            });
+        }
+
+        Ok((forces, virial))
+    }
+
+    /// Largest step for which the fastest lipid moves at most `max_displacement`, taking into account that its
+    /// velocity will grow by `F * dt` during the step: solves `|F| dt^2 + |v| dt = max_displacement` for each lipid.
+    fn adaptive_time_step(&self, forces: &[ExtForce], max_displacement: f32) -> f32 {
+        let mut result = f32::INFINITY;
+        for (l, f) in self.curr.lipids.iter().zip(forces) {
+            let rotation_lever = l.tail_length / FIRST_MOMENT;
+            let speed = l.linear_velocity.magnitude() + l.angular_velocity.abs() * rotation_lever + head_tail_attraction(l).magnitude();
+            let accel = f.force.magnitude() + f.torque.abs() * rotation_lever;
+            let time_step = if accel > f32::EPSILON {
+                (-speed + (speed * speed + 4.0 * accel * max_displacement).sqrt()) / (2.0 * accel)
+            } else if speed > f32::EPSILON {
+                max_displacement / speed
             } else {
-                (0.0_f32, 0.0_f32).into()
+                f32::INFINITY
             };
+            result = result.min(time_step);
+        }
+        result
+    }
+
+    /// Stretches the box and the lipids in `curr` as `barostat` says, unless some lipid can't follow: one that's frozen
+    /// and would end up out of bounds, or one that would go into an obstacle or out of the container.
+    fn rescale(&mut self, barostat: Barostat, time_step: f32) {
+        let factor = |pressure: f32| {
+            (1.0 + barostat.coupling * time_step * (pressure - barostat.target)).clamp(1.0 - MAX_BOX_SCALE, 1.0 + MAX_BOX_SCALE)
+        };
+        let pressure = self.curr.pressure;
+        let scale = match barostat.axes {
+            BoxAxes::Both => Vector::new(1.0, 1.0) * factor((pressure.x + pressure.y) / 2.0),
+            BoxAxes::X => Vector::new(factor(pressure.x), 1.0),
+            BoxAxes::Y => Vector::new(1.0, factor(pressure.y)),
+        };
+        let origin = self.bounds.0;
+        let stretch = |p: Point| origin + Vector::new((p.x - origin.x) * scale.x, (p.y - origin.y) * scale.y);
+        let bounds = (origin, stretch(self.bounds.1));
+
+        let walls = Walls::of(bounds, self.container.as_ref(), &self.obstacles);
+        let mut moved = Vec::with_capacity(self.curr.lipids.len());
+        for l in self.curr.lipids.iter() {
+            let (head, tail) = match self.curr.restraints.get(&l.id) {
+                Some(Restraint::Frozen) => (l.head_position, l.tail_position),
+                _ => (stretch(l.head_position), stretch(l.tail_position)),
+            };
+            if !walls.allow(l.head_position, head) || !walls.allow(l.tail_position, tail) {
+                return;
+            }
+            moved.push((head, tail));
+        }
+        for (l, (head, tail)) in self.curr.lipids.iter_mut().zip(moved) {
+            l.head_position = head;
+            l.tail_position = tail;
+        }
+        if grid_shape(bounds) != grid_shape(self.bounds) {
+            self.obstacle_water = obstacle_water(&self.obstacles, grid_shape(bounds));
+        }
+        self.bounds = bounds;
+    }
+
+    /// Largest distance any head or tail moved between `prev` and `curr`.
+    fn max_jump(&self) -> f32 {
+        self.prev
+            .lipids
+            .iter()
+            .zip(self.curr.lipids.iter())
+            .map(|(p, c)| {
+                p.head_position
+                    .distance(c.head_position)
+                    .max(p.tail_position.distance(c.tail_position))
+            })
+            .fold(0.0, f32::max)
+    }
+
+    fn integrate(&mut self, forces: &[ExtForce], time_step: f32) {
+        let walls = Walls::of(self.bounds, self.container.as_ref(), &self.obstacles);
+        for (l, f) in self.curr.lipids.iter_mut().zip(forces) {
+            if let Some(Restraint::Frozen) = self.curr.restraints.get(&l.id) {
+                l.linear_velocity = Vector::new(0.0, 0.0);
+                l.angular_velocity = 0.0;
+                continue;
+            }
+            let ExtForce {
+                force: ext_force,
+                torque: ext_torque,
+            } = *f;
+            let centre_of_mass = l.head_position + (l.tail_position - l.head_position) * CENTER_FRAC;
+            let head_tail_attraction = head_tail_attraction(l);
 
             let make_ccw_normal: Basis2<f32> = Rotation2::from_angle(Rad(0.5 * std::f32::consts::PI));
             let head_normal = make_ccw_normal.rotate_vector(centre_of_mass - l.head_position);
@@ -226,35 +938,185 @@ impl Engine {
             // F = m*a = m*Dv/Dt => Dv = (F/m)*Dt => v(t+Dt) = v(t) + (F/m)*Dt
             let new_lin_vel = l.linear_velocity + ext_force * time_step;
             let new_ang_vel = l.angular_velocity + ext_torque * time_step;
-            let head_vel = head_normal * new_ang_vel * l.tail_length * center_frac / first_moment + new_lin_vel + head_tail_attraction;
+            let head_vel = head_normal * new_ang_vel * l.tail_length * CENTER_FRAC / FIRST_MOMENT + new_lin_vel + head_tail_attraction;
             let tail_vel =
-                tail_normal * new_ang_vel * l.tail_length * (1.0 - center_frac) / first_moment + new_lin_vel - head_tail_attraction;
+                tail_normal * new_ang_vel * l.tail_length * (1.0 - CENTER_FRAC) / FIRST_MOMENT + new_lin_vel - head_tail_attraction;
 
             *l = Lipid {
                 // Ds = v*Dt => s(t + Dt) = s(t) + v*Dt
-                head_position: apply_velocity(self.bounds, l.head_position, head_vel * time_step),
-                tail_position: apply_velocity(self.bounds, l.tail_position, tail_vel * time_step),
-                linear_velocity: l.linear_velocity * friction_loss_frac + ext_force * time_step,
-                angular_velocity: l.angular_velocity * friction_loss_frac + ext_torque * time_step,
-                head_radius: l.head_radius,
-                tail_length: l.tail_length,
-                tail_width: l.tail_width,
+                head_position: walls.apply_velocity(l.head_position, head_vel * time_step),
+                tail_position: walls.apply_velocity(l.tail_position, tail_vel * time_step),
+                linear_velocity: l.linear_velocity * FRICTION_LOSS_FRAC + ext_force * time_step,
+                angular_velocity: l.angular_velocity * FRICTION_LOSS_FRAC + ext_torque * time_step,
+                ..*l
             }
         }
+    }
+}
 
-        self.curr.tick_time = Instant::now() - start_time;
+/// Largest force on any lipid that isn't frozen, counting a torque as the force it'd make at the end of the tail
+fn max_generalized_force(state: &State, forces: &[ExtForce]) -> f32 {
+    state
+        .lipids
+        .iter()
+        .zip(forces)
+        .filter(|(l, _)| state.restraints.get(&l.id) != Some(&Restraint::Frozen))
+        .map(|(l, f)| f.force.magnitude().max(f.torque.abs() / l.tail_length))
+        .fold(0.0, f32::max)
+}
+
+/// Half of what a force on `on` from `from` adds to the virial along x & y; the force back adds the other half.
+fn half_virial(on: Point, from: Point, force: Vector) -> Vector {
+    let r = on - from;
+    Vector::new(r.x * force.x, r.y * force.y) * 0.5
+}
+
+/// Velocity pulling/pushing the head and tail of the same lipid together/apart if they are too far from the natural distance
+fn head_tail_attraction(l: &Lipid) -> Vector {
+    let head_tail_distance2 = l.head_position.distance2(l.tail_position);
+    let head_tail_error2 = head_tail_distance2 - l.tail_length.powf(2.0);
+    if head_tail_error2.abs() > 0.1 {
+        let tail_to_head_unit = (l.tail_position - l.head_position) * head_tail_error2;
+        tail_to_head_unit / head_tail_distance2
+    } else {
+        (0.0_f32, 0.0_f32).into()
     }
+}
 
-    pub fn current_state(&self) -> State {
-        self.curr.clone()
+fn check_force(lipid: LipidId, term: Term, force: Vector, torque: f32) -> Result<(), SimError> {
+    match [force.x, force.y, torque].into_iter().find(|v| !v.is_finite()) {
+        Some(value) => Err(SimError::NonFinite { lipid, term, value }),
+        None => Ok(()),
     }
 }
 
-fn apply_velocity(bounds: (Point, Point), p: Point, v: Vector) -> Point {
-    let proposed = p + v;
-    if proposed.x > bounds.1.x || proposed.y > bounds.1.y || proposed.x < bounds.0.x || proposed.y < bounds.0.y {
+fn in_bounds(bounds: (Point, Point), p: Point) -> bool {
+    p.x >= bounds.0.x && p.y >= bounds.0.y && p.x <= bounds.1.x && p.y <= bounds.1.y
+}
+
+/// Everything that stops heads & tails moving wherever they like
+struct Walls<'a> {
+    bounds: (Point, Point),
+    container: Option<&'a Shape>,
+    obstacles: &'a [Obstacle],
+}
+
+impl<'a> Walls<'a> {
+    fn of(bounds: (Point, Point), container: Option<&'a Shape>, obstacles: &'a [Obstacle]) -> Self {
+        Self {
+            bounds,
+            container,
+            obstacles,
+        }
+    }
+
+    /// A move that would go out through the container's edge is reflected back in off it; other moves that would leave
+    /// the bounds or the container, or go into an obstacle, don't happen.
+    fn apply_velocity(&self, p: Point, v: Vector) -> Point {
+        let proposed = p + v;
+        if self.allow(p, proposed) {
+            return proposed;
+        }
+        if let Some(container) = self.container
+            && container.contains(p)
+        {
+            let (closest, distance) = container.nearest(proposed);
+            if distance > 0.0 {
+                let reflected = proposed - (proposed - closest) * 2.0;
+                if self.allow(p, reflected) {
+                    return reflected;
+                }
+            }
+        }
         p
-    } else {
-        proposed
+    }
+
+    /// Whether moving from `from` straight to `to` keeps clear of the walls. Points outside the container can move
+    /// freely, so anything that ends up out there can get back in.
+    fn allow(&self, from: Point, to: Point) -> bool {
+        let contained = self.container.is_none_or(|c| !c.contains(from) || c.encloses((from, to)));
+        in_bounds(self.bounds, to) && contained && !self.obstacles.iter().any(|o| o.blocks(from, to))
+    }
+}
+
+/// Rows & columns of the water grid for `bounds`: from 0 to the upper corner, and the margin
+fn grid_shape(bounds: (Point, Point)) -> (usize, usize) {
+    (
+        (bounds.1.y + GRID_MARGIN).ceil() as usize,
+        (bounds.1.x + GRID_MARGIN).ceil() as usize,
+    )
+}
+
+/// Each surface wets (hydrophilic, like heads do) or dries (hydrophobic, like tails do) the grid cells in and near it.
+fn obstacle_water(obstacles: &[Obstacle], shape: (usize, usize)) -> ::ndarray::Array2<f64> {
+    ::ndarray::Array2::from_shape_fn(shape, |(iy, ix)| {
+        let centre = Point::new(ix as f32 + 0.5, iy as f32 + 0.5);
+        obstacles
+            .iter()
+            .filter(|o| o.shape.nearest(centre).1 <= OBSTACLE_WATER_RANGE)
+            .map(|o| match o.surface {
+                Surface::Hydrophilic => 1.0,
+                Surface::Hydrophobic => -1.0,
+            })
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lipid(head: Point) -> Lipid {
+        Lipid {
+            id: 0,
+            species: 0,
+            head_position: head,
+            tail_position: head + Vector::new(0.0, 10.0),
+            linear_velocity: Vector::new(0.0, 0.0),
+            angular_velocity: 0.0,
+            head_radius: 2.0,
+            tail_length: 10.0,
+            tail_width: 1.5,
+        }
+    }
+
+    #[test]
+    fn minimization_leaves_frozen_lipids_where_they_are() {
+        let mut state = State::new();
+        // overlapping side by side, so each is pushed away from the other
+        let frozen = state.add_lipid(lipid(Point::new(200.0, 200.0)));
+        let free = state.add_lipid(lipid(Point::new(202.0, 200.0)));
+        state.restraints.insert(frozen, Restraint::Frozen);
+        let before = state.clone();
+        let mut e = Engine::new(state, Settings::default());
+        assert!(e.minimize(0.1, 100, 0.1).unwrap().iterations > 0);
+        let (a, b) = (before.lipid(frozen).unwrap(), e.state().lipid(frozen).unwrap());
+        assert_eq!((a.head_position, a.tail_position), (b.head_position, b.tail_position));
+        assert_ne!(
+            before.lipid(free).unwrap().head_position,
+            e.state().lipid(free).unwrap().head_position
+        );
+    }
+
+    #[test]
+    fn time_steps_have_to_be_positive_with_min_at_most_max() {
+        let adaptive = |min, max| Settings {
+            time_step: TimeStep::Adaptive {
+                max_displacement: 0.05,
+                min,
+                max,
+            },
+            ..Settings::default()
+        };
+        assert!(Settings::default().check().is_ok());
+        assert!(adaptive(1e-7, 1e-3).check().is_ok());
+        assert!(adaptive(1e-3, 1e-7).check().is_err());
+        assert!(adaptive(0.0, 1e-3).check().is_err());
+        assert!(adaptive(1e-7, f32::INFINITY).check().is_err());
+        let fixed = Settings {
+            time_step: TimeStep::Fixed(f32::NAN),
+            ..Settings::default()
+        };
+        assert!(fixed.check().is_err());
     }
 }
@@ -0,0 +1,246 @@
+//! Shapes the lipids share the box with (obstacles) or are kept inside (containers), and the distance helpers they're
+//! built on.
+//!
+//! Shapes are written as their name and numbers, and obstacles add the kind of surface, e.g.
+//! ```text
+//! segment 50 150 350 150 hydrophilic       # from (50, 150) to (350, 150)
+//! circle 200 200 30 hydrophobic            # centre (200, 200), radius 30
+//! polygon 100 100 300 100 300 120 hydrophilic  # corners, in either order; at least 3
+//! ```
+
+use crate::types::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// What an obstacle's surface does to the water around it: hydrophilic surfaces attract heads like heads do, and
+/// hydrophobic ones attract tails like tails do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Surface {
+    Hydrophilic,
+    Hydrophobic,
+}
+
+impl Surface {
+    pub fn name(self) -> &'static str {
+        match self {
+            Surface::Hydrophilic => "hydrophilic",
+            Surface::Hydrophobic => "hydrophobic",
+        }
+    }
+}
+
+impl FromStr for Surface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "hydrophilic" => Ok(Surface::Hydrophilic),
+            "hydrophobic" => Ok(Surface::Hydrophobic),
+            _ => Err(format!("unknown surface {s:?}, try hydrophilic or hydrophobic")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// A wall with no thickness
+    Segment(Point, Point),
+    Circle {
+        centre: Point,
+        radius: f32,
+    },
+    /// Solid, with the last corner joined back to the first
+    Polygon(Vec<Point>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Obstacle {
+    pub shape: Shape,
+    pub surface: Surface,
+}
+
+impl Shape {
+    /// Whether `p` is inside the solid; never for segments.
+    pub fn contains(&self, p: Point) -> bool {
+        match self {
+            Shape::Segment(..) => false,
+            Shape::Circle { centre, radius } => p.distance2(*centre) < radius * radius,
+            Shape::Polygon(corners) => polygon_contains(corners, p),
+        }
+    }
+
+    /// Closest point of the outline to `p`, and how far `p` is from it: negative inside.
+    pub fn nearest(&self, p: Point) -> (Point, f32) {
+        let (closest, distance) = match self {
+            Shape::Segment(a, b) => {
+                let closest = closest_on_segment(p, (*a, *b));
+                (closest, p.distance(closest))
+            }
+            Shape::Circle { centre, radius } => {
+                let offset = p - centre;
+                let length = offset.magnitude();
+                let direction = if length > 0.0 { offset / length } else { Vector::new(1.0, 0.0) };
+                (centre + direction * *radius, (length - radius).abs())
+            }
+            Shape::Polygon(corners) => edges(corners)
+                .map(|edge| {
+                    let closest = closest_on_segment(p, edge);
+                    (closest, p.distance(closest))
+                })
+                .fold((p, f32::INFINITY), |best, c| if c.1 < best.1 { c } else { best }),
+        };
+        (closest, if self.contains(p) { -distance } else { distance })
+    }
+
+    /// Shortest distance from the segment `s` to the shape; 0 if they touch or `s` is (partly) inside.
+    pub fn segment_distance(&self, s: (Point, Point)) -> f32 {
+        match self {
+            Shape::Segment(a, b) => segment_distance(s, (*a, *b)),
+            Shape::Circle { centre, radius } => (point_segment_distance(*centre, s) - radius).max(0.0),
+            Shape::Polygon(corners) => {
+                if self.contains(s.0) || self.contains(s.1) {
+                    return 0.0;
+                }
+                edges(corners).map(|edge| segment_distance(s, edge)).fold(f32::INFINITY, f32::min)
+            }
+        }
+    }
+
+    /// Whether the segment `s` is entirely inside the solid; never for segments.
+    pub fn encloses(&self, s: (Point, Point)) -> bool {
+        match self {
+            Shape::Segment(..) => false,
+            // a circle is convex
+            Shape::Circle { .. } => self.contains(s.0) && self.contains(s.1),
+            Shape::Polygon(corners) => self.contains(s.0) && self.contains(s.1) && !edges(corners).any(|edge| segments_cross(s, edge)),
+        }
+    }
+}
+
+impl FromStr for Shape {
+    type Err = String;
+
+    /// The shape's name, then its numbers
+    fn from_str(s: &str) -> Result<Self, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let [kind, numbers @ ..] = words.as_slice() else {
+            return Err("expected a shape and its numbers".to_string());
+        };
+        let numbers = numbers
+            .iter()
+            .map(|n| n.parse::<f32>().ok().filter(|n| n.is_finite()))
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| format!("bad number in {s:?}"))?;
+        let points = || numbers.chunks(2).map(|xy| Point::new(xy[0], xy[1])).collect::<Vec<_>>();
+        match (*kind, numbers.len()) {
+            ("segment", 4) => Ok(Shape::Segment(points()[0], points()[1])),
+            ("circle", 3) if numbers[2] > 0.0 => Ok(Shape::Circle {
+                centre: Point::new(numbers[0], numbers[1]),
+                radius: numbers[2],
+            }),
+            ("polygon", n) if n >= 6 && n.is_multiple_of(2) => Ok(Shape::Polygon(points())),
+            ("segment" | "circle" | "polygon", _) => Err(format!("wrong numbers for a {kind}: {s:?}")),
+            _ => Err(format!("unknown shape {kind:?}, try segment, circle or polygon")),
+        }
+    }
+}
+
+/// The same text `from_str` reads
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Shape::Segment(a, b) => write!(f, "segment {} {} {} {}", a.x, a.y, b.x, b.y),
+            Shape::Circle { centre, radius } => write!(f, "circle {} {} {radius}", centre.x, centre.y),
+            Shape::Polygon(corners) => {
+                write!(f, "polygon")?;
+                for p in corners {
+                    write!(f, " {} {}", p.x, p.y)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Obstacle {
+    /// Whether moving from `from` to `to` goes into or through the obstacle. Moves that start inside never are, so
+    /// anything that ends up in there can get out.
+    pub fn blocks(&self, from: Point, to: Point) -> bool {
+        if self.shape.contains(from) {
+            return false;
+        }
+        match &self.shape {
+            Shape::Segment(a, b) => segments_cross((from, to), (*a, *b)),
+            _ => self.shape.segment_distance((from, to)) <= 0.0,
+        }
+    }
+}
+
+impl FromStr for Obstacle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((shape, surface)) = s.trim().rsplit_once(char::is_whitespace) else {
+            return Err(format!("expected a shape, its numbers and a surface, not {s:?}"));
+        };
+        Ok(Self {
+            shape: shape.parse()?,
+            surface: surface.parse()?,
+        })
+    }
+}
+
+/// The same text `from_str` reads
+impl fmt::Display for Obstacle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.shape, self.surface.name())
+    }
+}
+
+fn edges(corners: &[Point]) -> impl Iterator<Item = (Point, Point)> + '_ {
+    corners.iter().zip(corners.iter().cycle().skip(1)).map(|(a, b)| (*a, *b))
+}
+
+/// Even-odd rule
+fn polygon_contains(corners: &[Point], p: Point) -> bool {
+    let mut inside = false;
+    for (a, b) in edges(corners) {
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn closest_on_segment(p: Point, (a, b): (Point, Point)) -> Point {
+    let ab = b - a;
+    let t = if ab.magnitude2() > 0.0 {
+        ((p - a).dot(ab) / ab.magnitude2()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    a + ab * t
+}
+
+pub fn point_segment_distance(p: Point, s: (Point, Point)) -> f32 {
+    p.distance(closest_on_segment(p, s))
+}
+
+/// Whether the two segments properly cross, i.e. each has its ends on either side of the other
+fn segments_cross(s: (Point, Point), t: (Point, Point)) -> bool {
+    let cross = |u: Vector, v: Vector| u.x * v.y - u.y * v.x;
+    let (d1, d2) = (s.1 - s.0, t.1 - t.0);
+    let (o1, o2) = (cross(d1, t.0 - s.0), cross(d1, t.1 - s.0));
+    let (o3, o4) = (cross(d2, s.0 - t.0), cross(d2, s.1 - t.0));
+    o1 * o2 < 0.0 && o3 * o4 < 0.0
+}
+
+pub fn segment_distance(s: (Point, Point), t: (Point, Point)) -> f32 {
+    if segments_cross(s, t) {
+        return 0.0;
+    }
+    point_segment_distance(s.0, t)
+        .min(point_segment_distance(s.1, t))
+        .min(point_segment_distance(t.0, s))
+        .min(point_segment_distance(t.1, s))
+}
@@ -1,8 +1,82 @@
+//! Starting states. `default` is the original hand-placed one; `generate` builds others from a generator name and
+//! `key = value` parameters, as given on the command line or in a scenario file (see `scenario`).
+
+use crate::analysis::Shape;
+use crate::geometry::{self, Obstacle, Surface, point_segment_distance, segment_distance};
 use crate::types::*;
 use cgmath::Basis2;
 use cgmath::Rad;
 use cgmath::Rotation;
 use cgmath::Rotation2;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Names accepted by `generate`
+pub const NAMES: &[&str] = &["default", "gas", "random", "bilayer", "micelle", "vesicle", "lattice", "image"];
+
+/// Parameter names to their (unparsed) values
+pub type Params = BTreeMap<String, String>;
+
+/// Gap between the tail ends of facing leaflets
+const LEAFLET_GAP: f32 = 1.0;
+/// Random placements tried per lipid before giving up on reaching the density asked for
+const ATTEMPTS_PER_LIPID: usize = 1000;
+/// Points tried around each one in Poisson-disk sampling before it's retired (Bridson's k)
+const POISSON_ATTEMPTS: usize = 30;
+/// Orientations tried for each Poisson-disk point before giving up on it
+const ORIENTATION_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InitError {
+    UnknownGenerator(String),
+    /// A parameter the generator doesn't take
+    UnknownParam {
+        generator: String,
+        param: String,
+    },
+    BadValue {
+        param: String,
+        value: String,
+    },
+    /// Random placement ran out of attempts
+    Crowded {
+        placed: usize,
+        wanted: usize,
+    },
+    /// The lipids wouldn't fit in the bounds
+    OutOfBounds,
+    MissingParam(String),
+    /// Two parameters that can't both be given
+    Conflicting(String, String),
+    /// The mask image couldn't be read
+    Image {
+        path: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitError::UnknownGenerator(name) => write!(f, "unknown initial configuration {name}, try one of {NAMES:?}"),
+            InitError::UnknownParam { generator, param } => write!(f, "{generator} doesn't take a parameter {param}"),
+            InitError::BadValue { param, value } => write!(f, "bad value {value:?} for {param}"),
+            InitError::Crowded { placed, wanted } => {
+                write!(f, "could only fit {placed} of {wanted} lipids without overlaps, ask for fewer")
+            }
+            InitError::OutOfBounds => write!(f, "lipids would end up out of bounds, make it smaller"),
+            InitError::MissingParam(param) => write!(f, "{param} has to be given"),
+            InitError::Conflicting(a, b) => write!(f, "give either {a} or {b}, not both"),
+            InitError::Image { path, message } => write!(f, "can't read {path}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
 
 pub fn default() -> State {
     let mut result = State::new();
@@ -14,17 +88,791 @@ pub fn default() -> State {
             let angle = if irow % 2 == 0 { 1.0 } else { 0.0 };
             let rot: Basis2<f32> = Rotation2::from_angle(Rad(angle * std::f32::consts::PI));
             let offset_vec = rot.rotate_vector(tail_vec_x);
-            result.lipids.push(Lipid {
+            result.add_lipid(Lipid {
+                id: 0,
+                species: 0,
                 head_position: centre - offset_vec,
                 tail_position: centre + offset_vec,
                 linear_velocity: Vector2::new(0.0, 0.0),
                 angular_velocity: 0.0,
                 head_radius: 3.0,
-                tail_length: tail_length,
+                tail_length,
                 tail_width: 1.,
-            })
+            });
         }
     }
 
     result
 }
+
+/// What every generated lipid looks like. Parameters `head_radius`, `tail_length`, `tail_width` & `species`.
+#[derive(Debug, Copy, Clone)]
+pub struct Template {
+    pub head_radius: f32,
+    pub tail_length: f32,
+    pub tail_width: f32,
+    pub species: u16,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self {
+            head_radius: 3.0,
+            tail_length: 10.0,
+            tail_width: 1.0,
+            species: 0,
+        }
+    }
+}
+
+impl Template {
+    /// At rest, with the head at `head` and the tail pointing along `direction` (a unit vector)
+    pub fn lipid(&self, head: Point, direction: Vector) -> Lipid {
+        Lipid {
+            id: 0,
+            species: self.species,
+            head_position: head,
+            tail_position: head + direction * self.tail_length,
+            linear_velocity: Vector::new(0.0, 0.0),
+            angular_velocity: 0.0,
+            head_radius: self.head_radius,
+            tail_length: self.tail_length,
+            tail_width: self.tail_width,
+        }
+    }
+
+    /// Of the head disc plus the tail strip, for packing fractions
+    pub fn area(&self) -> f32 {
+        PI * self.head_radius * self.head_radius + self.tail_length * self.tail_width
+    }
+
+    /// Centred on `centre` rather than by the head
+    pub fn lipid_centred(&self, centre: Point, direction: Vector) -> Lipid {
+        self.lipid(centre - direction * (self.tail_length / 2.0), direction)
+    }
+}
+
+/// Keeps track of which parameters were used, so that misspelled ones can be reported rather than silently ignored.
+struct Reader<'a> {
+    generator: &'a str,
+    params: &'a Params,
+    used: Vec<&'static str>,
+}
+
+impl<'a> Reader<'a> {
+    fn get<T: FromStr>(&mut self, param: &'static str, default: T) -> Result<T, InitError> {
+        Ok(self.optional(param)?.unwrap_or(default))
+    }
+
+    fn optional<T: FromStr>(&mut self, param: &'static str) -> Result<Option<T>, InitError> {
+        self.used.push(param);
+        self.params
+            .get(param)
+            .map(|value| {
+                value.parse().map_err(|_| InitError::BadValue {
+                    param: param.to_string(),
+                    value: value.clone(),
+                })
+            })
+            .transpose()
+    }
+
+    fn positive(&mut self, param: &'static str, default: f32) -> Result<f32, InitError> {
+        let value = self.get(param, default)?;
+        if value > 0.0 {
+            Ok(value)
+        } else {
+            Err(InitError::BadValue {
+                param: param.to_string(),
+                value: value.to_string(),
+            })
+        }
+    }
+
+    fn finish(self) -> Result<(), InitError> {
+        match self.params.keys().find(|k| !self.used.contains(&k.as_str())) {
+            Some(param) => Err(InitError::UnknownParam {
+                generator: self.generator.to_string(),
+                param: param.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Builds the named configuration inside `bounds`. Generators place things around `centre_x`, `centre_y` (the middle of
+/// the bounds by default) and take `angle` in degrees where there's a direction to choose.
+///
+/// - `default`: `initialization::default()`, no parameters
+/// - `gas`: lipids at random positions & orientations, none overlapping, `density` per unit area
+/// - `random`: like `gas`, but asked for by `count` or `packing_fraction` (of the bounds covered by heads & tails), and
+///   placed by `method` `rejection` (uniformly random) or `poisson` (Poisson-disk sampling, evenly spread)
+/// - `bilayer`: a flat bilayer `length` long, lipids `spacing` apart in each leaflet
+/// - `micelle`: `count` lipids in a ring, heads out
+/// - `vesicle`: a closed bilayer with heads `radius` from the centre on the outside, lipids `spacing` apart
+/// - `lattice`: `rows` x `cols` parallel lipids, `spacing` apart along a row and `row_spacing` apart across
+/// - `image`: regions painted in the image at `path`, stretched over the bounds, with black ones becoming obstacles with
+///   a `surface` (`hydrophilic` by default); see `from_mask`
+///
+/// Along with the state comes any obstacles the configuration has, for the engine.
+pub fn generate(name: &str, params: &Params, bounds: (Point, Point), rng: &mut impl Rng) -> Result<(State, Vec<Obstacle>), InitError> {
+    let mut p = Reader {
+        generator: name,
+        params,
+        used: vec![],
+    };
+    let defaults = Template::default();
+    let template = Template {
+        head_radius: p.positive("head_radius", defaults.head_radius)?,
+        tail_length: p.positive("tail_length", defaults.tail_length)?,
+        tail_width: p.positive("tail_width", defaults.tail_width)?,
+        species: p.get("species", defaults.species)?,
+    };
+    let middle = bounds.0 + (bounds.1 - bounds.0) / 2.0;
+    let centre = Point::new(p.get("centre_x", middle.x)?, p.get("centre_y", middle.y)?);
+    let default_spacing = 2.0 * template.head_radius;
+
+    let mut obstacles = vec![];
+    let lipids = match name {
+        "default" => {
+            return match params.keys().next() {
+                Some(param) => Err(InitError::UnknownParam {
+                    generator: name.to_string(),
+                    param: param.clone(),
+                }),
+                None => Ok((default(), vec![])),
+            };
+        }
+        "gas" => {
+            let density: f32 = p.positive("density", 0.002)?;
+            p.finish()?;
+            let size = bounds.1 - bounds.0;
+            gas(&template, (density * size.x * size.y).round() as usize, bounds, rng)?
+        }
+        "random" => {
+            let count: Option<usize> = p.optional("count")?;
+            let packing_fraction: Option<f32> = p.optional("packing_fraction")?;
+            let method: String = p.get("method", "rejection".to_string())?;
+            p.finish()?;
+            let size = bounds.1 - bounds.0;
+            let count = match (count, packing_fraction) {
+                (Some(_), Some(_)) => return Err(InitError::Conflicting("count".into(), "packing_fraction".into())),
+                (Some(count), None) => count,
+                (None, fraction) => (fraction.unwrap_or(0.1) * size.x * size.y / template.area()).round() as usize,
+            };
+            match method.as_str() {
+                "rejection" => gas(&template, count, bounds, rng)?,
+                "poisson" => poisson(&template, count, bounds, rng)?,
+                _ => {
+                    return Err(InitError::BadValue {
+                        param: "method".into(),
+                        value: method,
+                    });
+                }
+            }
+        }
+        "bilayer" => {
+            let length = p.positive("length", 200.0)?;
+            let spacing = p.positive("spacing", default_spacing)?;
+            let angle: f32 = p.get("angle", 0.0)?;
+            p.finish()?;
+            bilayer(&template, centre, length, spacing, angle.to_radians())
+        }
+        "micelle" => {
+            let count = p.get("count", 20)?;
+            p.finish()?;
+            micelle(&template, centre, count)
+        }
+        "vesicle" => {
+            let radius = p.positive("radius", 60.0)?;
+            let spacing = p.positive("spacing", default_spacing)?;
+            p.finish()?;
+            vesicle(&template, centre, radius, spacing)?
+        }
+        "lattice" => {
+            let rows = p.get("rows", 4)?;
+            let cols = p.get("cols", 10)?;
+            let spacing = p.positive("spacing", default_spacing)?;
+            let row_spacing = p.positive("row_spacing", template.tail_length + 2.0 * template.head_radius + 1.0)?;
+            let angle: f32 = p.get("angle", 90.0)?;
+            p.finish()?;
+            lattice(&template, centre, rows, cols, spacing, row_spacing, angle.to_radians())
+        }
+        "image" => {
+            let path: String = p.get("path", String::new())?;
+            let density = p.positive("density", 0.002)?;
+            let spacing = p.positive("spacing", default_spacing)?;
+            let surface = p.get("surface", Surface::Hydrophilic)?;
+            p.finish()?;
+            if path.is_empty() {
+                return Err(InitError::MissingParam("path".into()));
+            }
+            let mask = image::open(&path)
+                .map_err(|err| InitError::Image {
+                    path: path.clone(),
+                    message: err.to_string(),
+                })?
+                .to_rgb8();
+            obstacles = mask_obstacles(&mask, bounds, surface);
+            from_mask(&template, &mask, density, spacing, bounds, rng)?
+        }
+        _ => return Err(InitError::UnknownGenerator(name.to_string())),
+    };
+
+    let inside = |q: Point| q.x >= bounds.0.x && q.y >= bounds.0.y && q.x <= bounds.1.x && q.y <= bounds.1.y;
+    if !lipids.iter().all(|l| inside(l.head_position) && inside(l.tail_position)) {
+        return Err(InitError::OutOfBounds);
+    }
+    let mut state = State::new();
+    for l in lipids {
+        state.add_lipid(l);
+    }
+    Ok((state, obstacles))
+}
+
+fn unit(angle: f32) -> Vector {
+    Vector::new(angle.cos(), angle.sin())
+}
+
+fn gas(template: &Template, count: usize, bounds: (Point, Point), rng: &mut impl Rng) -> Result<Vec<Lipid>, InitError> {
+    let reach = template.tail_length / 2.0 + template.head_radius;
+    let (lo, hi) = (bounds.0 + Vector::new(reach, reach), bounds.1 - Vector::new(reach, reach));
+    if lo.x >= hi.x || lo.y >= hi.y {
+        return Err(InitError::OutOfBounds);
+    }
+    let mut lipids = Vec::with_capacity(count);
+    scatter(
+        template,
+        count,
+        &mut lipids,
+        |rng| Point::new(rng.gen_range(lo.x..hi.x), rng.gen_range(lo.y..hi.y)),
+        |_| true,
+        rng,
+    )?;
+    Ok(lipids)
+}
+
+/// Adds `count` lipids to `lipids`, centred on points from `sample`, pointing every which way, where they're `allowed` and
+/// don't overlap any lipid already there.
+fn scatter<R: Rng>(
+    template: &Template,
+    count: usize,
+    lipids: &mut Vec<Lipid>,
+    mut sample: impl FnMut(&mut R) -> Point,
+    allowed: impl Fn(&Lipid) -> bool,
+    rng: &mut R,
+) -> Result<(), InitError> {
+    let mut occupancy = Occupancy::new(template, lipids);
+    let mut placed = 0;
+    for _ in 0..count.saturating_mul(ATTEMPTS_PER_LIPID) {
+        if placed == count {
+            break;
+        }
+        let centre = sample(rng);
+        let candidate = template.lipid_centred(centre, unit(rng.gen_range(0.0..2.0 * PI)));
+        if allowed(&candidate) && !occupancy.overlaps(&candidate) {
+            occupancy.insert(candidate);
+            lipids.push(candidate);
+            placed += 1;
+        }
+    }
+    if placed < count {
+        return Err(InitError::Crowded { placed, wanted: count });
+    }
+    Ok(())
+}
+
+/// `count` lipids at evenly spread random points (Bridson's Poisson-disk sampling), each turned whichever random way
+/// doesn't overlap its neighbours. The spacing between points starts out at what should give about `count` of them, and
+/// shrinks until there are enough; any extra are dropped at random.
+fn poisson<R: Rng>(template: &Template, count: usize, bounds: (Point, Point), rng: &mut R) -> Result<Vec<Lipid>, InitError> {
+    let reach = template.tail_length / 2.0 + template.head_radius;
+    let (lo, hi) = (bounds.0 + Vector::new(reach, reach), bounds.1 - Vector::new(reach, reach));
+    if lo.x >= hi.x || lo.y >= hi.y {
+        return Err(InitError::OutOfBounds);
+    }
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    // disks of radius spacing / 2 fill about 70% of the area when Poisson-disk sampled
+    let size = hi - lo;
+    let mut spacing = (0.7 * 4.0 / PI * size.x * size.y / count as f32).sqrt();
+    let mut most = 0;
+    while spacing > template.tail_width / 2.0 {
+        let mut lipids = poisson_disk(template, spacing, (lo, hi), rng);
+        if lipids.len() >= count {
+            lipids.shuffle(rng);
+            lipids.truncate(count);
+            return Ok(lipids);
+        }
+        most = most.max(lipids.len());
+        spacing *= 0.9;
+    }
+    Err(InitError::Crowded {
+        placed: most,
+        wanted: count,
+    })
+}
+
+fn poisson_disk<R: Rng>(template: &Template, spacing: f32, (lo, hi): (Point, Point), rng: &mut R) -> Vec<Lipid> {
+    // at most one point per cell, so only the cells within two of a point's can hold points closer than `spacing`
+    let cell_size = spacing / 2.0_f32.sqrt();
+    let cell = |p: Point| (((p.x - lo.x) / cell_size) as i32, ((p.y - lo.y) / cell_size) as i32);
+    let mut points: HashMap<(i32, i32), Point> = HashMap::new();
+    let mut occupancy = Occupancy::new(template, &[]);
+    let mut lipids = vec![];
+    let mut active = vec![];
+
+    let mut try_place = |p: Point, rng: &mut R, lipids: &mut Vec<Lipid>| -> bool {
+        let (cx, cy) = cell(p);
+        let crowded =
+            (-2..=2).any(|dy| (-2..=2).any(|dx| points.get(&(cx + dx, cy + dy)).is_some_and(|q| q.distance2(p) < spacing * spacing)));
+        if crowded {
+            return false;
+        }
+        for _ in 0..ORIENTATION_ATTEMPTS {
+            let candidate = template.lipid_centred(p, unit(rng.gen_range(0.0..2.0 * PI)));
+            if !occupancy.overlaps(&candidate) {
+                points.insert((cx, cy), p);
+                occupancy.insert(candidate);
+                lipids.push(candidate);
+                return true;
+            }
+        }
+        false
+    };
+
+    let first = Point::new(rng.gen_range(lo.x..hi.x), rng.gen_range(lo.y..hi.y));
+    if try_place(first, rng, &mut lipids) {
+        active.push(first);
+    }
+    while !active.is_empty() {
+        let iactive = rng.gen_range(0..active.len());
+        let around = active[iactive];
+        let mut found = false;
+        for _ in 0..POISSON_ATTEMPTS {
+            let p = around + unit(rng.gen_range(0.0..2.0 * PI)) * rng.gen_range(spacing..2.0 * spacing);
+            if p.x < lo.x || p.y < lo.y || p.x >= hi.x || p.y >= hi.y {
+                continue;
+            }
+            if try_place(p, rng, &mut lipids) {
+                active.push(p);
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            active.swap_remove(iactive);
+        }
+    }
+    lipids
+}
+
+/// Lipids bucketed by midpoint, so that overlap checks only look at the ones nearby.
+struct Occupancy {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Lipid>>,
+}
+
+impl Occupancy {
+    /// Holding `lipids`, with cells big enough for them and for lipids like `template`
+    fn new(template: &Template, lipids: &[Lipid]) -> Self {
+        let reach = |head_radius: f32, tail_length: f32, tail_width: f32| tail_length / 2.0 + head_radius.max(tail_width / 2.0);
+        let most = lipids
+            .iter()
+            .map(|l| reach(l.head_radius, l.tail_length, l.tail_width))
+            .fold(reach(template.head_radius, template.tail_length, template.tail_width), f32::max);
+        let mut result = Self {
+            cell_size: 2.0 * most,
+            cells: HashMap::new(),
+        };
+        for l in lipids {
+            result.insert(*l);
+        }
+        result
+    }
+
+    fn cell(&self, p: Point) -> (i32, i32) {
+        ((p.x / self.cell_size).floor() as i32, (p.y / self.cell_size).floor() as i32)
+    }
+
+    fn insert(&mut self, l: Lipid) {
+        self.cells.entry(self.cell(l.midpoint())).or_default().push(l);
+    }
+
+    /// Lipids that overlap have midpoints at most two reaches apart, i.e. in neighbouring cells.
+    fn overlaps(&self, candidate: &Lipid) -> bool {
+        let (cx, cy) = self.cell(candidate.midpoint());
+        (-1..=1).any(|dy| {
+            (-1..=1).any(|dx| {
+                self.cells
+                    .get(&(cx + dx, cy + dy))
+                    .is_some_and(|ls| ls.iter().any(|l| overlaps(l, candidate)))
+            })
+        })
+    }
+}
+
+/// One leaflet on each side of the line through `centre` along `angle`, tails meeting in the middle.
+fn bilayer(template: &Template, centre: Point, length: f32, spacing: f32, angle: f32) -> Vec<Lipid> {
+    let along = unit(angle);
+    let normal = Vector::new(along.y, -along.x);
+    let count = (length / spacing).floor().max(1.0) as usize;
+    let mut lipids = vec![];
+    for side in [1.0, -1.0] {
+        let head_offset = normal * side * (template.tail_length + LEAFLET_GAP / 2.0);
+        for i in 0..count {
+            let s = (i as f32 + 0.5) * spacing - count as f32 * spacing / 2.0;
+            lipids.push(template.lipid(centre + along * s + head_offset, -normal * side));
+        }
+    }
+    lipids
+}
+
+/// Heads on a circle just big enough to fit them `2 * head_radius` apart, and no smaller than the tails are long.
+fn micelle(template: &Template, centre: Point, count: usize) -> Vec<Lipid> {
+    let radius = (count as f32 * 2.0 * template.head_radius / (2.0 * PI)).max(template.tail_length);
+    ring(template, centre, radius, count, true)
+}
+
+/// The outer leaflet's heads are at `radius`; the inner leaflet faces it, tails to tails.
+fn vesicle(template: &Template, centre: Point, radius: f32, spacing: f32) -> Result<Vec<Lipid>, InitError> {
+    let inner_radius = radius - 2.0 * template.tail_length - LEAFLET_GAP;
+    if inner_radius < 2.0 * template.head_radius {
+        return Err(InitError::BadValue {
+            param: "radius".into(),
+            value: radius.to_string(),
+        });
+    }
+    let count = |r: f32| ((2.0 * PI * r / spacing).floor() as usize).max(1);
+    let mut lipids = ring(template, centre, radius, count(radius), true);
+    lipids.extend(ring(template, centre, inner_radius, count(inner_radius), false));
+    Ok(lipids)
+}
+
+/// `count` lipids with their heads evenly around a circle, tails pointing in or out.
+fn ring(template: &Template, centre: Point, radius: f32, count: usize, heads_out: bool) -> Vec<Lipid> {
+    (0..count)
+        .map(|i| {
+            let outward = unit(2.0 * PI * i as f32 / count as f32);
+            let direction = if heads_out { -outward } else { outward };
+            template.lipid(centre + outward * radius, direction)
+        })
+        .collect()
+}
+
+/// Lipids pointing along `angle`, in rows across that direction.
+fn lattice(template: &Template, centre: Point, rows: usize, cols: usize, spacing: f32, row_spacing: f32, angle: f32) -> Vec<Lipid> {
+    let direction = unit(angle);
+    let across = Vector::new(-direction.y, direction.x);
+    let mut lipids = vec![];
+    for row in 0..rows {
+        for col in 0..cols {
+            let offset = across * ((col as f32 - (cols as f32 - 1.0) / 2.0) * spacing)
+                + direction * ((row as f32 - (rows as f32 - 1.0) / 2.0) * row_spacing);
+            lipids.push(template.lipid_centred(centre + offset, direction));
+        }
+    }
+    lipids
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Region {
+    Empty,
+    FillA,
+    FillB,
+    Bilayer,
+    Obstacle,
+}
+
+/// Each colour channel counts as on from half brightness up.
+fn region_of(pixel: image::Rgb<u8>) -> Region {
+    let [r, g, b] = pixel.0.map(|c| c >= 128);
+    match (r, g, b) {
+        (true, false, false) => Region::FillA,
+        (false, false, true) => Region::FillB,
+        (false, true, false) => Region::Bilayer,
+        (false, false, false) => Region::Obstacle,
+        _ => Region::Empty,
+    }
+}
+
+/// Lipids placed as sketched in `mask`, which is stretched over the bounds:
+/// - red areas are filled with randomly placed lipids of the template's species, `density` per unit area
+/// - blue areas likewise, with the next species
+/// - each connected green stroke becomes a straight bilayer along it, as long as the stroke, lipids `spacing` apart
+/// - black areas are kept clear of lipids (and are made into obstacles by `mask_obstacles`)
+/// - anything else (e.g. white) is left empty
+fn from_mask<R: Rng>(
+    template: &Template,
+    mask: &image::RgbImage,
+    density: f32,
+    spacing: f32,
+    bounds: (Point, Point),
+    rng: &mut R,
+) -> Result<Vec<Lipid>, InitError> {
+    let (width, height) = mask.dimensions();
+    let size = bounds.1 - bounds.0;
+    let pixel_size = Vector::new(size.x / width as f32, size.y / height as f32);
+    let pixel_centre = |(x, y): (u32, u32)| bounds.0 + Vector::new((x as f32 + 0.5) * pixel_size.x, (y as f32 + 0.5) * pixel_size.y);
+    let region_at = |p: Point| {
+        let (x, y) = ((p.x - bounds.0.x) / pixel_size.x, (p.y - bounds.0.y) / pixel_size.y);
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+            None
+        } else {
+            Some(region_of(*mask.get_pixel(x as u32, y as u32)))
+        }
+    };
+    let pixels = |region: Region| -> Vec<(u32, u32)> {
+        mask.enumerate_pixels()
+            .filter(|(_, _, p)| region_of(**p) == region)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    };
+
+    let mut lipids = vec![];
+    for stroke in connected(&pixels(Region::Bilayer)) {
+        let shape = Shape::of(stroke.iter().map(|p| pixel_centre(*p)));
+        let along = shape.major_axis;
+        let projections = stroke.iter().map(|p| (pixel_centre(*p) - shape.centroid).dot(along));
+        let (min, max) = projections.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), s| (lo.min(s), hi.max(s)));
+        let centre = shape.centroid + along * ((min + max) / 2.0);
+        let length = max - min + pixel_size.x.min(pixel_size.y);
+        lipids.extend(bilayer(template, centre, length, spacing, along.y.atan2(along.x)));
+    }
+
+    // a lipid is allowed if every part of it is in bounds and out of obstacles
+    let allowed = |l: &Lipid| {
+        [0.0, 0.5, 1.0]
+            .into_iter()
+            .all(|frac| matches!(region_at(l.tail_point(frac)), Some(r) if r != Region::Obstacle))
+    };
+    if !lipids.iter().all(allowed) {
+        return Err(InitError::OutOfBounds);
+    }
+    for (region, species) in [(Region::FillA, template.species), (Region::FillB, template.species + 1)] {
+        let area = pixels(region);
+        if area.is_empty() {
+            continue;
+        }
+        let count = (density * area.len() as f32 * pixel_size.x * pixel_size.y).round() as usize;
+        let template = Template { species, ..*template };
+        let sample = |rng: &mut R| {
+            let p = pixel_centre(area[rng.gen_range(0..area.len())]);
+            p + Vector::new(rng.gen_range(-0.5..0.5) * pixel_size.x, rng.gen_range(-0.5..0.5) * pixel_size.y)
+        };
+        scatter(
+            &template,
+            count,
+            &mut lipids,
+            sample,
+            |l| allowed(l) && region_at(l.midpoint()) == Some(region),
+            rng,
+        )?;
+    }
+    Ok(lipids)
+}
+
+/// A corner of the pixel grid, in half pixels so that cuts can go down the middle of a column
+type Corner = (i64, i64);
+
+/// The black areas of `mask`, stretched over the bounds like in `from_mask`, as one solid polygon each (pixels touching
+/// only at a corner are apart): the outline traced along the pixel edges. Holes are joined to the outline around them
+/// by a cut of no width up the middle of a column, which the even-odd rule leaves out of the solid.
+fn mask_obstacles(mask: &image::RgbImage, bounds: (Point, Point), surface: Surface) -> Vec<Obstacle> {
+    let (width, height) = (mask.width() as i64, mask.height() as i64);
+    let size = bounds.1 - bounds.0;
+    let point = |(x, y): Corner| bounds.0 + Vector::new(x as f32 * size.x / (2 * width) as f32, y as f32 * size.y / (2 * height) as f32);
+    let black = |x: i64, y: i64| {
+        (0..width).contains(&x) && (0..height).contains(&y) && region_of(*mask.get_pixel(x as u32, y as u32)) == Region::Obstacle
+    };
+
+    // each side between a black pixel and anything else, going clockwise (on screen) around the black
+    let mut sides: BTreeMap<Corner, Vec<Corner>> = BTreeMap::new();
+    for y in 0..height {
+        for x in 0..width {
+            if !black(x, y) {
+                continue;
+            }
+            let (left, top, right, bottom) = (2 * x, 2 * y, 2 * x + 2, 2 * y + 2);
+            for (neighbour, from, to) in [
+                ((x, y - 1), (left, top), (right, top)),
+                ((x + 1, y), (right, top), (right, bottom)),
+                ((x, y + 1), (right, bottom), (left, bottom)),
+                ((x - 1, y), (left, bottom), (left, top)),
+            ] {
+                if !black(neighbour.0, neighbour.1) {
+                    sides.entry(from).or_default().push(to);
+                }
+            }
+        }
+    }
+
+    // followed around into closed outlines, keeping only the corners. Where black pixels touch only at a corner, two
+    // sides leave it; turning right (into the black) keeps to the pixel the outline came along.
+    let next = |(from, to): (Corner, Corner)| {
+        let right = (to.0 - (to.1 - from.1), to.1 + (to.0 - from.0));
+        let ends = &sides[&to];
+        (to, if ends.contains(&right) { right } else { ends[0] })
+    };
+    let mut followed = BTreeSet::new();
+    // clockwise ones go around black areas, anticlockwise ones around holes in them
+    let mut outlines: Vec<Vec<Corner>> = vec![];
+    let mut holes: Vec<Vec<Corner>> = vec![];
+    for (&from, ends) in sides.iter() {
+        for &to in ends {
+            let first = (from, to);
+            if followed.contains(&first) {
+                continue;
+            }
+            let mut outline = vec![];
+            let mut side = first;
+            loop {
+                followed.insert(side);
+                let after = next(side);
+                let turns = (side.1.0 - side.0.0) * (after.1.1 - after.0.1) - (side.1.1 - side.0.1) * (after.1.0 - after.0.0);
+                if turns != 0 {
+                    outline.push(side.1);
+                }
+                if after == first {
+                    break;
+                }
+                side = after;
+            }
+            let area: i64 = outline
+                .iter()
+                .zip(outline.iter().cycle().skip(1))
+                .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+                .sum();
+            if area > 0 { outlines.push(outline) } else { holes.push(outline) }
+        }
+    }
+
+    // Each hole is cut up to the side above it, from the middle of its top left pixel. That side belongs to the outline
+    // around the hole, or to a hole higher up that's already been joined to it.
+    holes.sort_by_key(|h| h.iter().map(|c| c.1).min());
+    for hole in holes {
+        let top = hole.iter().map(|c| c.1).min().unwrap_or(0);
+        // holes go anticlockwise, so along their top to the left
+        let (ends_at, &(left, _)) = hole
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.1 == top)
+            .min_by_key(|(_, c)| c.0)
+            .expect("the top row has corners");
+        let x = left + 1;
+        let mut y = top / 2 - 1;
+        while black(left / 2, y) {
+            y -= 1;
+        }
+        let (above, below) = ((x, 2 * y + 2), (x, top));
+        let found = outlines.iter().enumerate().find_map(|(i, outline)| {
+            (0..outline.len())
+                .find(|&j| {
+                    let (a, b) = (outline[j], outline[(j + 1) % outline.len()]);
+                    a.1 == above.1 && b.1 == above.1 && a.0 < x && x < b.0
+                })
+                .map(|j| (i, j))
+        });
+        let Some((i, j)) = found else {
+            continue;
+        };
+        let mut joined = vec![above, below];
+        joined.extend(hole[ends_at..].iter().chain(hole[..ends_at].iter()));
+        joined.extend([below, above]);
+        outlines[i].splice(j + 1..j + 1, joined);
+    }
+
+    outlines
+        .into_iter()
+        .map(|outline| Obstacle {
+            shape: geometry::Shape::Polygon(outline.into_iter().map(point).collect()),
+            surface,
+        })
+        .collect()
+}
+
+/// Groups of pixels touching each other, diagonals included
+fn connected(pixels: &[(u32, u32)]) -> Vec<Vec<(u32, u32)>> {
+    let mut remaining: BTreeSet<(u32, u32)> = pixels.iter().copied().collect();
+    let mut groups = vec![];
+    while let Some(start) = remaining.pop_first() {
+        let mut group = vec![start];
+        let mut next = 0;
+        while next < group.len() {
+            let (x, y) = group[next];
+            next += 1;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let neighbour = (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy));
+                    if remaining.remove(&neighbour) {
+                        group.push(neighbour);
+                    }
+                }
+            }
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Heads are discs and tails are segments `tail_width` wide; two lipids overlap if any of those touch.
+pub fn overlaps(a: &Lipid, b: &Lipid) -> bool {
+    let heads = a.head_position.distance(b.head_position) < a.head_radius + b.head_radius;
+    let tails =
+        segment_distance((a.head_position, a.tail_position), (b.head_position, b.tail_position)) < (a.tail_width + b.tail_width) / 2.0;
+    let head_a_tail_b = point_segment_distance(a.head_position, (b.head_position, b.tail_position)) < a.head_radius + b.tail_width / 2.0;
+    let head_b_tail_a = point_segment_distance(b.head_position, (a.head_position, a.tail_position)) < b.head_radius + a.tail_width / 2.0;
+    heads || tails || head_a_tail_b || head_b_tail_a
+}
+
+/// Takes out every lipid poking out of the container or touching one of the obstacles; gives how many there were.
+pub fn remove_obstructed(state: &mut State, container: Option<&geometry::Shape>, obstacles: &[Obstacle]) -> usize {
+    let obstructed: Vec<LipidId> = state
+        .lipids
+        .iter()
+        .filter(|l| {
+            let outside =
+                container.is_some_and(|c| !c.encloses((l.head_position, l.tail_position)) || -c.nearest(l.head_position).1 < l.head_radius);
+            outside
+                || obstacles.iter().any(|o| {
+                    o.shape.nearest(l.head_position).1 < l.head_radius
+                        || o.shape.segment_distance((l.head_position, l.tail_position)) < l.tail_width / 2.0
+                })
+        })
+        .map(|l| l.id)
+        .collect();
+    for id in obstructed.iter() {
+        state.remove_lipid(*id);
+    }
+    obstructed.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_obstacles_cover_just_the_black_pixels() {
+        // a ring with a blob in its hole, a square with two holes side by side, and two pixels touching at a corner
+        let black = |x: i32, y: i32| {
+            let r2 = (x - 12).pow(2) + (y - 12).pow(2);
+            let ring = (36..100).contains(&r2) || r2 < 4;
+            let square = (26..38).contains(&x) && (4..16).contains(&y) && !((28..31).contains(&x) && (7..12).contains(&y));
+            let square = square && !((32..36).contains(&x) && (6..14).contains(&y));
+            ring || square || (x, y) == (5, 28) || (x, y) == (6, 29)
+        };
+        let mask = image::RgbImage::from_fn(40, 32, |x, y| {
+            image::Rgb(if black(x as i32, y as i32) { [0, 0, 0] } else { [255, 255, 255] })
+        });
+        let obstacles = mask_obstacles(&mask, (Point::new(0.0, 0.0), Point::new(80.0, 64.0)), Surface::Hydrophilic);
+        assert_eq!(obstacles.len(), 5);
+        for y in 0..32 {
+            for x in 0..40 {
+                let centre = Point::new(2.0 * x as f32 + 1.0, 2.0 * y as f32 + 1.0);
+                let inside = obstacles.iter().filter(|o| o.shape.contains(centre)).count();
+                assert_eq!(inside, black(x, y) as usize, "pixel ({x}, {y})");
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! The simulation itself, and reading & writing what it produces. The viewer lives in the binary.
+
+pub mod analysis;
+pub mod checkpoint;
+pub mod engine;
+pub mod geometry;
+pub mod initialization;
+pub mod mltraj;
+pub mod observables;
+pub mod scenario;
+pub mod trajectory;
+pub mod types;
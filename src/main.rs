@@ -8,32 +8,266 @@ use piston::ButtonEvent;
 use piston::EventLoop;
 
 use std::cmp::max;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::thread;
 
+use macrolipid::geometry::{Obstacle, Shape};
+use macrolipid::types::*;
+use macrolipid::{engine, initialization, mltraj, observables, scenario, trajectory};
+use rand::SeedableRng;
+
+mod analyze;
 mod app;
-mod engine;
-mod initialization;
-mod types;
-use types::*;
+mod replay;
 
 fn main() {
-    let mut window: GlutinWindow = WindowSettings::new("Macrolipid", [400, 400])
-        .graphics_api(OpenGL::V4_2)
-        .build()
-        .unwrap();
+    let mut settings = engine::Settings::default();
+    let mut headless_ticks = None;
+    let mut resume_from: Option<PathBuf> = None;
+    let mut replay_from: Option<PathBuf> = None;
+    let mut analyze: Option<(String, PathBuf, PathBuf)> = None;
+    let mut analyze_options = analyze::Options::default();
+    let mut outputs = Outputs::default();
+    let mut trajectory_paths: Vec<PathBuf> = vec![];
+    let mut quantize = false;
+    let mut scenario_path: Option<PathBuf> = None;
+    let mut init_params = initialization::Params::new();
+    let mut minimize: Option<Minimize> = None;
+    let mut observables_path: Option<PathBuf> = None;
+    let mut observable_names: Vec<String> = observables::NAMES.iter().map(|name| name.to_string()).collect();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--adaptive" => {
+                settings.time_step = engine::TimeStep::Adaptive {
+                    max_displacement: 0.05,
+                    min: 1e-7,
+                    max: 1e-3,
+                }
+            }
+            "--headless" => headless_ticks = Some(parse_value(&arg, args.next())),
+            "--resume" => resume_from = Some(parse_value(&arg, args.next())),
+            "--replay" => replay_from = Some(parse_value(&arg, args.next())),
+            "--analyze" => {
+                let name = parse_value(&arg, args.next());
+                analyze = Some((name, parse_value(&arg, args.next()), parse_value(&arg, args.next())));
+            }
+            "--bin-width" => analyze_options.bin_width = parse_value(&arg, args.next()),
+            "--max-r" => analyze_options.max_r = parse_value(&arg, args.next()),
+            "--max-lag" => analyze_options.max_lag = Some(parse_value(&arg, args.next())),
+            "--checkpoint" => outputs.checkpoint = Some(parse_value(&arg, args.next())),
+            "--checkpoint-every" => outputs.checkpoint_every = parse_value(&arg, args.next()),
+            "--trajectory" => trajectory_paths.push(parse_value(&arg, args.next())),
+            "--trajectory-every" => outputs.trajectory_every = parse_value(&arg, args.next()),
+            "--quantize" => quantize = true,
+            "--observables" => observables_path = Some(parse_value(&arg, args.next())),
+            "--observables-every" => outputs.observables_every = parse_value(&arg, args.next()),
+            "--observe" => {
+                let names: String = parse_value(&arg, args.next());
+                observable_names = names.split(',').map(String::from).collect();
+            }
+            "--seed" => settings.seed = parse_value(&arg, args.next()),
+            "--barostat" => settings.barostat.get_or_insert_default().target = parse_value(&arg, args.next()),
+            "--barostat-coupling" => settings.barostat.get_or_insert_default().coupling = parse_value(&arg, args.next()),
+            "--barostat-axes" => settings.barostat.get_or_insert_default().axes = parse_value(&arg, args.next()),
+            "--minimize" => minimize = Some(minimize.unwrap_or_default()),
+            "--minimize-tolerance" => minimize.get_or_insert_default().tolerance = parse_value(&arg, args.next()),
+            "--minimize-iterations" => minimize.get_or_insert_default().max_iterations = parse_value(&arg, args.next()),
+            "--scenario" => scenario_path = Some(parse_value(&arg, args.next())),
+            "--init" => {
+                init_params.insert(scenario::INIT_KEY.to_string(), parse_value(&arg, args.next()));
+            }
+            "--init-param" => {
+                let param: String = parse_value(&arg, args.next());
+                let Some((key, value)) = param.split_once('=') else {
+                    exit_with(&format!("{arg} takes key=value, not {param}"));
+                };
+                init_params.insert(key.to_string(), value.to_string());
+            }
+            _ => exit_with(&format!("unknown argument: {arg}")),
+        }
+    }
+
+    if let Err(err) = settings.check() {
+        exit_with(&err);
+    }
+
+    if let Some((name, input, output)) = analyze {
+        if let Err(err) = analyze::run(&name, &input, &output, &analyze_options) {
+            exit_with(&format!("can't analyze {}: {err}", input.display()));
+        }
+        return;
+    }
+
+    if let Some(path) = replay_from {
+        let replay = replay::Replay::open(&path).unwrap_or_else(|err| exit_with(&format!("can't replay {}: {err}", path.display())));
+        let (container, obstacles) = replay.walls();
+        run_viewer(None, Some(replay), container, obstacles);
+        return;
+    }
+
+    let resuming = resume_from.is_some();
+    let mut e = match resume_from {
+        Some(path) => {
+            // refused rather than ignored, as they would make the resumed run differ from the original
+            if settings != engine::Settings::default() {
+                exit_with("--adaptive, --seed & --barostat can't be given when resuming: the checkpoint has its settings");
+            }
+            File::open(&path)
+                .and_then(|f| engine::Engine::from_checkpoint(&mut BufReader::new(f)))
+                .unwrap_or_else(|err| exit_with(&format!("can't resume from {}: {err}", path.display())))
+        }
+        None => {
+            let Setup {
+                state,
+                container,
+                obstacles,
+                fields,
+            } = setup(scenario_path.as_deref(), init_params, settings.seed);
+            let mut e = engine::Engine::new(state, settings);
+            e.set_container(container);
+            e.set_obstacles(obstacles);
+            e.set_fields(fields);
+            e
+        }
+    };
+
+    if let Some(minimize) = minimize {
+        if resuming {
+            exit_with("--minimize is for new runs, not resumed ones");
+        }
+        match e.minimize(minimize.tolerance, minimize.max_iterations, minimize.max_step) {
+            Ok(result) => eprintln!(
+                "minimization {} after {} iterations, max force {}",
+                if result.converged { "converged" } else { "stopped" },
+                result.iterations,
+                result.max_force
+            ),
+            Err(err) => exit_with(&format!("minimization failed: {err}")),
+        }
+    }
+
+    for path in trajectory_paths {
+        let trajectory = open_trajectory(&path, &e, resuming, quantize)
+            .unwrap_or_else(|err| exit_with(&format!("can't open {}: {err}", path.display())));
+        outputs.trajectories.push(trajectory);
+    }
+
+    if let Some(path) = observables_path {
+        let log = open_observable_log(&path, &observable_names, e.fields().pull.as_ref(), resuming, e.ticks())
+            .unwrap_or_else(|err| exit_with(&format!("can't open {}: {err}", path.display())));
+        outputs.observables = Some(log);
+    }
 
-    let (tx, rx) = mpsc::sync_channel::<State>(1);
+    if let Some(ticks) = headless_ticks {
+        run_headless(e, ticks, outputs);
+        return;
+    }
+
+    let container = e.container().cloned();
+    let obstacles = e.obstacles().to_vec();
+    let (tx, rx) = mpsc::sync_channel::<Update>(1);
     thread::spawn(move || {
-        let mut e = engine::Engine::new(initialization::default());
+        let mut e = e;
         loop {
-            e.tick();
-            tx.try_send(e.current_state()).ok();
+            if let Err(err) = e.tick() {
+                eprintln!("simulation stopped: {err}");
+                tx.send(Err(err)).ok();
+                return;
+            }
+            if let Err(err) = outputs.after_tick(&mut e) {
+                eprintln!("can't write outputs: {err}");
+            }
+            tx.try_send(Ok((e.current_state(), e.bounds()))).ok();
             // tx.send(e.current_state()).ok();
         }
     });
 
+    run_viewer(Some(rx), None, container, obstacles);
+}
+
+/// How to relax the starting state before dynamics
+struct Minimize {
+    tolerance: f32,
+    max_iterations: usize,
+    max_step: f32,
+}
+
+impl Default for Minimize {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.1,
+            max_iterations: 5000,
+            max_step: 0.1,
+        }
+    }
+}
+
+/// What a new run starts from
+struct Setup {
+    state: State,
+    container: Option<Shape>,
+    obstacles: Vec<Obstacle>,
+    fields: engine::Fields,
+}
+
+/// Reads the scenario file, if any, with parameters given on the command line taking precedence, and builds the starting
+/// state. Lipids the generator put outside the container or in an obstacle are left out.
+fn setup(scenario_path: Option<&Path>, overrides: initialization::Params, seed: u64) -> Setup {
+    let mut params = match scenario_path {
+        Some(path) => scenario::read(path).unwrap_or_else(|err| exit_with(&format!("can't read {}: {err}", path.display()))),
+        None => initialization::Params::new(),
+    };
+    params.extend(overrides);
+    let container = scenario::take_container(&mut params).unwrap_or_else(|err| exit_with(&format!("bad container: {err}")));
+    let mut obstacles = scenario::take_obstacles(&mut params).unwrap_or_else(|err| exit_with(&format!("bad obstacle: {err}")));
+    let mut fields = scenario::take_fields(&mut params).unwrap_or_else(|err| exit_with(&format!("bad field: {err}")));
+    let pull = scenario::take_pull(&mut params).unwrap_or_else(|err| exit_with(&format!("bad pull: {err}")));
+    let restraints = scenario::take_restraints(&mut params).unwrap_or_else(|err| exit_with(&format!("bad restraint: {err}")));
+    let tags = scenario::take_tags(&mut params).unwrap_or_else(|err| exit_with(&format!("bad tag: {err}")));
+    let name = params.remove(scenario::INIT_KEY).unwrap_or_else(|| "default".to_string());
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(seed);
+    let (mut state, generated) = initialization::generate(&name, &params, engine::DEFAULT_BOUNDS, &mut rng)
+        .unwrap_or_else(|err| exit_with(&format!("can't set up {name}: {err}")));
+    obstacles.extend(generated);
+    let removed = initialization::remove_obstructed(&mut state, container.as_ref(), &obstacles);
+    if removed > 0 {
+        eprintln!("left out {removed} lipids that were outside the container or in obstacles");
+    }
+    for (tag, selection) in tags {
+        for id in selection.select(&state) {
+            state.tags.insert(id, tag.clone());
+        }
+    }
+    if let Some(pull) = pull {
+        fields.pull = Some(pull.resolve(&state).unwrap_or_else(|err| exit_with(&format!("bad pull: {err}"))));
+    }
+    // before any minimization, so it leaves frozen lipids where they are
+    state.restraints = restraints.resolve(&state);
+    Setup {
+        state,
+        container,
+        obstacles,
+        fields,
+    }
+}
+
+/// What the engine thread sends the viewer after a tick: the state with the bounds it's in, or why it stopped
+type Update = Result<(State, (Point, Point)), engine::SimError>;
+
+/// Shows states as they come from a live engine thread, or from a recording.
+fn run_viewer(rx: Option<mpsc::Receiver<Update>>, mut replay: Option<replay::Replay>, container: Option<Shape>, obstacles: Vec<Obstacle>) {
+    let mut window: GlutinWindow = WindowSettings::new("Macrolipid", [400, 400])
+        .graphics_api(OpenGL::V4_2)
+        .build()
+        .unwrap();
+
     let mut app = app::App::new();
+    app.set_walls(container, obstacles);
 
     let mut old_fps = 60;
     let mut events = Events::new(EventSettings::new().max_fps(60));
@@ -43,18 +277,36 @@ fn main() {
             app.render(&args, event_settings.max_fps);
         }
 
-        if let Ok(state) = rx.try_recv() {
-            app.new_data(state)
+        match rx.as_ref().map(mpsc::Receiver::try_recv) {
+            Some(Ok(Ok((state, bounds)))) => app.new_data(state, bounds),
+            Some(Ok(Err(err))) => app.new_error(err),
+            _ => (),
         }
 
         if let Some(args) = e.update_args() {
             app.update(&args);
+            if let Some(replay) = &mut replay {
+                replay.update(args.dt);
+                match replay.new_frame() {
+                    Ok(Some((state, bounds))) => app.new_data(state, bounds),
+                    Ok(None) => (),
+                    Err(err) => exit_with(&format!("can't read frame: {err}")),
+                }
+                app.set_status(replay.status());
+            }
         }
 
+        #[allow(clippy::collapsible_if)]
         if let Some(args) = e.button_args() {
             if let Keyboard(key) = args.button {
                 use piston::ButtonState::*;
                 use piston::Key;
+                if args.state == Press
+                    && let Some(replay) = &mut replay
+                    && replay.key_pressed(key)
+                {
+                    continue;
+                }
                 match (key, args.state) {
                     (Key::Comma, Press) => events.set_max_fps(max(event_settings.max_fps, 4) - 2),
                     (Key::Period, Press) => events.set_max_fps(event_settings.max_fps + 2),
@@ -63,6 +315,7 @@ fn main() {
                         events.set_max_fps(2);
                     }
                     (Key::S, Release) => events.set_max_fps(old_fps),
+                    (Key::D, Press) => app.toggle_director_field(),
                     (Key::Q, Press) => break 'main_loop,
                     (Key::Escape, Press) => break 'main_loop,
                     _ => (),
@@ -71,3 +324,168 @@ fn main() {
         }
     }
 }
+
+/// Everything that gets written out while the simulation runs.
+struct Outputs {
+    checkpoint: Option<PathBuf>,
+    checkpoint_every: u64,
+    last_checkpoint_tick: Option<u64>,
+    trajectories: Vec<Box<dyn trajectory::TrajectoryOutput + Send>>,
+    trajectory_every: u64,
+    observables: Option<observables::ObservableLog<BufWriter<File>>>,
+    observables_every: u64,
+}
+
+impl Default for Outputs {
+    fn default() -> Self {
+        Self {
+            checkpoint: None,
+            checkpoint_every: 10000,
+            last_checkpoint_tick: None,
+            trajectories: vec![],
+            trajectory_every: 1000,
+            observables: None,
+            observables_every: 100,
+        }
+    }
+}
+
+impl Outputs {
+    fn after_tick(&mut self, e: &mut engine::Engine) -> io::Result<()> {
+        if let Some(log) = &mut self.observables
+            && e.ticks().is_multiple_of(self.observables_every)
+        {
+            log.log(e.state(), e.bounds())?;
+        }
+        if e.ticks().is_multiple_of(self.trajectory_every) {
+            for trajectory in self.trajectories.iter_mut() {
+                trajectory.write_frame(e.state(), e.bounds())?;
+            }
+        }
+        if e.ticks().is_multiple_of(self.checkpoint_every) {
+            self.write_checkpoint(e)?;
+        }
+        Ok(())
+    }
+
+    /// Writes next to the old checkpoint and then replaces it, so being interrupted mid-write loses nothing.
+    fn write_checkpoint(&mut self, e: &mut engine::Engine) -> io::Result<()> {
+        let Some(path) = &self.checkpoint else {
+            return Ok(());
+        };
+        // writing reseeds the engine, so doing it twice at the same tick would make this run diverge from a resumed one
+        if self.last_checkpoint_tick == Some(e.ticks()) {
+            return Ok(());
+        }
+        let partial_path = path.with_extension("partial");
+        e.write_checkpoint(&mut BufWriter::new(File::create(&partial_path)?))?;
+        std::fs::rename(partial_path, path)?;
+        self.last_checkpoint_tick = Some(e.ticks());
+        Ok(())
+    }
+}
+
+/// Picks the format from the extension. A resumed run carries on with the trajectories the original run was writing, from
+/// the tick it resumed at.
+fn open_trajectory(
+    path: &Path,
+    e: &engine::Engine,
+    resuming: bool,
+    quantize: bool,
+) -> io::Result<Box<dyn trajectory::TrajectoryOutput + Send>> {
+    if path.extension().is_some_and(|extension| extension == "mltraj") {
+        if resuming && path.exists() {
+            return Ok(Box::new(mltraj::Writer::append(path, e.ticks())?));
+        }
+        let header = mltraj::Header {
+            quantized: quantize,
+            seed: e.settings().seed,
+            bounds: e.bounds(),
+            container: e.container().cloned(),
+            obstacles: e.obstacles().to_vec(),
+            params: format!("{:?}", e.settings()),
+        };
+        return Ok(Box::new(mltraj::Writer::new(header, BufWriter::new(File::create(path)?))?));
+    }
+
+    let Some(format) = trajectory::Format::from_path(path) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown trajectory format"));
+    };
+    if resuming && path.exists() {
+        trajectory::cut_after(path, format, e.ticks())?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resuming)
+        .truncate(!resuming)
+        .open(path)?;
+    Ok(Box::new(trajectory::TrajectoryWriter::new(format, BufWriter::new(file))))
+}
+
+/// A pull's force is logged along with the observables named. A resumed run carries on with the log from `tick`.
+fn open_observable_log(
+    path: &Path,
+    names: &[String],
+    pull: Option<&engine::Pull>,
+    resuming: bool,
+    tick: u64,
+) -> io::Result<observables::ObservableLog<BufWriter<File>>> {
+    let invalid_input = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let format = observables::LogFormat::from_path(path).ok_or_else(|| invalid_input("unknown log format".into()))?;
+    let mut observables: Vec<Box<dyn observables::Observable + Send>> = names
+        .iter()
+        .map(|name| {
+            observables::by_name(name)
+                .ok_or_else(|| invalid_input(format!("unknown observable {name}, try one of {:?}", observables::NAMES)))
+        })
+        .collect::<io::Result<_>>()?;
+    if let Some(pull) = pull {
+        observables.push(Box::new(observables::PullForce(pull.clone())));
+    }
+    if resuming && path.exists() {
+        observables::cut_after(path, format, tick)?;
+    }
+    // a log cut back to nothing needs its CSV header again
+    let appending = resuming && path.metadata().is_ok_and(|metadata| metadata.len() > 0);
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(appending)
+        .truncate(!appending)
+        .open(path)?;
+    Ok(observables::ObservableLog::new(
+        format,
+        BufWriter::new(file),
+        observables,
+        appending,
+    ))
+}
+
+/// Runs the engine without a window until it has done `ticks` ticks in total (counting those before a resume), or
+/// until it fails.
+fn run_headless(mut e: engine::Engine, ticks: u64, mut outputs: Outputs) {
+    while e.ticks() < ticks {
+        if let Err(err) = e.tick() {
+            exit_with(&format!("simulation stopped at tick {}: {err}", e.ticks()));
+        }
+        if let Err(err) = outputs.after_tick(&mut e) {
+            exit_with(&format!("can't write outputs: {err}"));
+        }
+    }
+    if let Err(err) = outputs.write_checkpoint(&mut e) {
+        exit_with(&format!("can't write checkpoint: {err}"));
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: Option<String>) -> T {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(v)) => v,
+        _ => exit_with(&format!("{arg} needs a valid value")),
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
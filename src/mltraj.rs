@@ -0,0 +1,636 @@
+//! macrolipid's own binary trajectory format (`.mltraj`), much smaller than the text ones and seekable.
+//!
+//! Everything is little-endian:
+//! ```text
+//! header: magic "MLTRAJ\0\0" | version u32 | quantized u8 | seed u64 | bounds (4 x f32) | container | obstacle count u32
+//!         | obstacles | params (u32 length + UTF-8)
+//! blocks: tag u8 | length of the rest of the block u32 | ...
+//!   b'F' frame: tick u64 | time f64 | time_step f32 | bounds (4 x f32) | next lipid id u32 | lipid count u64 | lipids
+//!               | tag count u32 | tags
+//!   b'I' index: frame count u64 | frame offsets (u64 each) | offset of this block u64 | "MLTRIDX\0"
+//! ```
+//! The header has the bounds the run started with, and each frame the bounds at that tick, which a barostat changes.
+//! The container, obstacles, lipids and tags are stored like in checkpoints. Quantized lipids only keep id, species,
+//! head & tail positions, as `u16` fractions of the frame's bounds, and head radius, tail length & tail width as `u16`
+//! multiples of 1/256; velocities read back as 0.
+//!
+//! The index is written when a writer is finished, so readers normally jump straight to it from the end of the file. If
+//! it's missing (the run was interrupted) or stale (frames were written after it), readers scan the blocks instead.
+
+use crate::checkpoint::*;
+use crate::geometry::{Obstacle, Shape};
+use crate::trajectory::TrajectoryOutput;
+use crate::types::*;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"MLTRAJ\0\0";
+const INDEX_MAGIC: &[u8; 8] = b"MLTRIDX\0";
+const VERSION: u32 = 1;
+const FRAME_TAG: u8 = b'F';
+const INDEX_TAG: u8 = b'I';
+const SHAPE_SCALE: f32 = 256.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub quantized: bool,
+    pub seed: u64,
+    /// The bounds the run started with; frames have their own
+    pub bounds: (Point, Point),
+    pub container: Option<Shape>,
+    pub obstacles: Vec<Obstacle>,
+    /// Free-form description of how the run was set up
+    pub params: String,
+}
+
+impl Header {
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, VERSION)?;
+        w.write_all(&[self.quantized as u8])?;
+        write_u64(w, self.seed)?;
+        write_point(w, self.bounds.0)?;
+        write_point(w, self.bounds.1)?;
+        write_walls(w, self.container.as_ref(), &self.obstacles)?;
+        write_string(w, &self.params)
+    }
+
+    fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a macrolipid trajectory"));
+        }
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(invalid_data(&format!("unsupported trajectory version {version}")));
+        }
+        let mut quantized = [0u8];
+        r.read_exact(&mut quantized)?;
+        let seed = read_u64(r)?;
+        let bounds = (read_point(r)?, read_point(r)?);
+        let (container, obstacles) = read_walls(r)?;
+        Ok(Self {
+            quantized: quantized[0] != 0,
+            seed,
+            bounds,
+            container,
+            obstacles,
+            params: read_string(r)?,
+        })
+    }
+}
+
+pub struct Writer<W: Write> {
+    header: Header,
+    out: W,
+    /// Where `out` is in the file
+    position: u64,
+    frame_offsets: Vec<u64>,
+    finished: bool,
+}
+
+impl Writer<File> {
+    /// Carries on writing a trajectory that already exists, with the header it already has, for a run resumed at `tick`.
+    /// The file is cut after its last whole frame up to that tick: later frames are written again by the resumed run, and
+    /// an index or a block an interrupted run didn't finish would be in the way.
+    pub fn append(path: &Path, tick: u64) -> io::Result<Self> {
+        let mut existing = Reader::open(path)?;
+        let mut kept = 0;
+        while kept < existing.len() && existing.read_tick(kept)? <= tick {
+            kept += 1;
+        }
+        let end = match kept {
+            0 => existing.first_block,
+            _ => existing.block_end(kept - 1)?,
+        };
+        let file = File::options().append(true).open(path)?;
+        file.set_len(end)?;
+        existing.frame_offsets.truncate(kept);
+        Ok(Self {
+            header: existing.header,
+            position: end,
+            out: file,
+            frame_offsets: existing.frame_offsets,
+            finished: false,
+        })
+    }
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(header: Header, mut out: W) -> io::Result<Self> {
+        let mut bytes = vec![];
+        header.write(&mut bytes)?;
+        out.write_all(&bytes)?;
+        Ok(Self {
+            header,
+            out,
+            position: bytes.len() as u64,
+            frame_offsets: vec![],
+            finished: false,
+        })
+    }
+
+    pub fn write_frame(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        let mut body = vec![];
+        write_u64(&mut body, state.tick)?;
+        write_f64(&mut body, state.time)?;
+        write_f32(&mut body, state.time_step)?;
+        write_point(&mut body, bounds.0)?;
+        write_point(&mut body, bounds.1)?;
+        write_u32(&mut body, state.next_id)?;
+        write_u64(&mut body, state.lipids.len() as u64)?;
+        for l in state.lipids.iter() {
+            write_identity(&mut body, l)?;
+            if self.header.quantized {
+                write_quantized_lipid(&mut body, l, bounds)?;
+            } else {
+                write_lipid(&mut body, l)?;
+            }
+        }
+        write_tags(&mut body, state)?;
+        self.frame_offsets.push(self.position);
+        self.write_block(FRAME_TAG, &body)?;
+        self.out.flush()
+    }
+
+    /// Writes the index, so readers don't have to scan. Writing more frames afterwards is fine, but makes the index stale.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut body = vec![];
+        write_u64(&mut body, self.frame_offsets.len() as u64)?;
+        for offset in self.frame_offsets.iter() {
+            write_u64(&mut body, *offset)?;
+        }
+        write_u64(&mut body, self.position)?;
+        body.extend_from_slice(INDEX_MAGIC);
+        self.write_block(INDEX_TAG, &body)?;
+        self.finished = true;
+        self.out.flush()
+    }
+
+    fn write_block(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        self.out.write_all(&[tag])?;
+        write_u32(&mut self.out, body.len() as u32)?;
+        self.out.write_all(body)?;
+        self.position += 1 + 4 + body.len() as u64;
+        self.finished = false;
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for Writer<W> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish().ok();
+        }
+    }
+}
+
+impl<W: Write> TrajectoryOutput for Writer<W> {
+    fn write_frame(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        Writer::write_frame(self, state, bounds)
+    }
+}
+
+/// Random access to the frames of a `.mltraj` file.
+pub struct Reader<R: Read + Seek> {
+    header: Header,
+    input: R,
+    /// Where the blocks start, after the header
+    first_block: u64,
+    frame_offsets: Vec<u64>,
+}
+
+impl Reader<BufReader<File>> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Reader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let header = Header::read(&mut input)?;
+        let first_block = input.stream_position()?;
+        let end = input.seek(SeekFrom::End(0))?;
+        let frame_offsets = match read_index(&mut input, end)? {
+            Some(frame_offsets) => frame_offsets,
+            None => scan_frames(&mut input, first_block, end)?,
+        };
+        Ok(Self {
+            header,
+            input,
+            first_block,
+            frame_offsets,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.frame_offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_offsets.is_empty()
+    }
+
+    fn frame_offset(&self, iframe: usize) -> io::Result<u64> {
+        self.frame_offsets
+            .get(iframe)
+            .copied()
+            .ok_or_else(|| invalid_data(&format!("there is no frame {iframe}")))
+    }
+
+    /// Just the tick of frame `iframe`
+    fn read_tick(&mut self, iframe: usize) -> io::Result<u64> {
+        let offset = self.frame_offset(iframe)?;
+        self.input.seek(SeekFrom::Start(offset + 1 + 4))?;
+        read_u64(&mut self.input)
+    }
+
+    /// Where the block after frame `iframe` starts
+    fn block_end(&mut self, iframe: usize) -> io::Result<u64> {
+        let offset = self.frame_offset(iframe)?;
+        self.input.seek(SeekFrom::Start(offset + 1))?;
+        Ok(offset + 1 + 4 + read_u32(&mut self.input)? as u64)
+    }
+
+    /// The state at frame `iframe`, with the bounds it was in
+    pub fn read_frame(&mut self, iframe: usize) -> io::Result<(State, (Point, Point))> {
+        let offset = self.frame_offset(iframe)?;
+        self.input.seek(SeekFrom::Start(offset + 1 + 4))?;
+        let r = &mut self.input;
+        let mut state = State::new();
+        state.tick = read_u64(r)?;
+        state.time = read_f64(r)?;
+        state.time_step = read_f32(r)?;
+        let bounds = (read_point(r)?, read_point(r)?);
+        state.next_id = read_u32(r)?;
+        let num_lipids = read_u64(r)?;
+        for _ in 0..num_lipids {
+            let (id, species) = read_identity(r)?;
+            let l = if self.header.quantized {
+                read_quantized_lipid(r, bounds)?
+            } else {
+                read_lipid(r)?
+            };
+            state.lipids.push(Lipid { id, species, ..l });
+        }
+        read_tags(r, &mut state)?;
+        Ok((state, bounds))
+    }
+}
+
+/// The index at the end of the file, unless there isn't one, or frames were written after it.
+fn read_index(input: &mut (impl Read + Seek), end: u64) -> io::Result<Option<Vec<u64>>> {
+    if end < 16 {
+        return Ok(None);
+    }
+    input.seek(SeekFrom::Start(end - 16))?;
+    let index_offset = read_u64(input)?;
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != INDEX_MAGIC || index_offset >= end {
+        return Ok(None);
+    }
+    input.seek(SeekFrom::Start(index_offset + 1 + 4))?;
+    let num_frames = read_u64(input)?;
+    (0..num_frames).map(|_| read_u64(input)).collect::<io::Result<_>>().map(Some)
+}
+
+fn scan_frames(input: &mut (impl Read + Seek), first_block: u64, end: u64) -> io::Result<Vec<u64>> {
+    let mut frame_offsets = vec![];
+    let mut offset = first_block;
+    // a block cut short by an interrupted write is ignored
+    while offset + 1 + 4 <= end {
+        input.seek(SeekFrom::Start(offset))?;
+        let mut tag = [0u8];
+        input.read_exact(&mut tag)?;
+        let length = read_u32(input)? as u64;
+        let next = offset + 1 + 4 + length;
+        if next > end {
+            break;
+        }
+        match tag[0] {
+            FRAME_TAG => frame_offsets.push(offset),
+            INDEX_TAG => (),
+            _ => return Err(invalid_data(&format!("unknown block at {offset}"))),
+        }
+        offset = next;
+    }
+    Ok(frame_offsets)
+}
+
+fn write_quantized_lipid(w: &mut impl Write, l: &Lipid, bounds: (Point, Point)) -> io::Result<()> {
+    for p in [l.head_position, l.tail_position] {
+        write_u16(w, quantize(p.x, bounds.0.x, bounds.1.x))?;
+        write_u16(w, quantize(p.y, bounds.0.y, bounds.1.y))?;
+    }
+    for v in [l.head_radius, l.tail_length, l.tail_width] {
+        write_u16(w, (v * SHAPE_SCALE).round().clamp(0.0, u16::MAX as f32) as u16)?;
+    }
+    Ok(())
+}
+
+fn read_quantized_lipid(r: &mut impl Read, bounds: (Point, Point)) -> io::Result<Lipid> {
+    Ok(Lipid {
+        id: 0,
+        species: 0,
+        head_position: read_quantized_point(r, bounds)?,
+        tail_position: read_quantized_point(r, bounds)?,
+        linear_velocity: Vector::new(0.0, 0.0),
+        angular_velocity: 0.0,
+        head_radius: read_u16(r)? as f32 / SHAPE_SCALE,
+        tail_length: read_u16(r)? as f32 / SHAPE_SCALE,
+        tail_width: read_u16(r)? as f32 / SHAPE_SCALE,
+    })
+}
+
+fn read_quantized_point(r: &mut impl Read, bounds: (Point, Point)) -> io::Result<Point> {
+    Ok(Point::new(
+        dequantize(read_u16(r)?, bounds.0.x, bounds.1.x),
+        dequantize(read_u16(r)?, bounds.0.y, bounds.1.y),
+    ))
+}
+
+fn quantize(v: f32, lo: f32, hi: f32) -> u16 {
+    ((v - lo) / (hi - lo) * u16::MAX as f32).round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+fn dequantize(v: u16, lo: f32, hi: f32) -> f32 {
+    lo + v as f32 / u16::MAX as f32 * (hi - lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Surface;
+    use std::io::Cursor;
+
+    const BOUNDS: (Point, Point) = (Point { x: 3.0, y: 3.0 }, Point { x: 397.0, y: 397.0 });
+    /// Wider than `BOUNDS`, like after a barostat stretched the box
+    const STRETCHED: (Point, Point) = (Point { x: 3.0, y: 3.0 }, Point { x: 601.5, y: 397.0 });
+
+    fn header(quantized: bool) -> Header {
+        Header {
+            quantized,
+            seed: 7,
+            bounds: BOUNDS,
+            container: Some(Shape::Circle {
+                centre: Point::new(200.0, 200.0),
+                radius: 190.0,
+            }),
+            obstacles: vec![Obstacle {
+                shape: Shape::Polygon(vec![Point::new(10.0, 10.0), Point::new(30.0, 10.0), Point::new(20.0, 25.0)]),
+                surface: Surface::Hydrophobic,
+            }],
+            params: "test".to_string(),
+        }
+    }
+
+    /// Lipids with awkward numbers, a gap in the ids and a tag. The last one is outside `BOUNDS` but inside `STRETCHED`.
+    fn state(tick: u64) -> State {
+        let mut state = State::new();
+        state.tick = tick;
+        state.time = tick as f64 * 1e-4;
+        state.time_step = 1e-4;
+        for i in 0..5u16 {
+            let x = 10.1 + 110.3 * i as f32 + tick as f32 / 7.0;
+            state.add_lipid(Lipid {
+                id: 0,
+                species: i % 2,
+                head_position: Point::new(x, 100.0 + 0.37 * i as f32),
+                tail_position: Point::new(x + 7.9, 106.1),
+                linear_velocity: Vector::new(0.5, -1.25 / (i + 1) as f32),
+                angular_velocity: 0.3 * i as f32,
+                head_radius: 2.0,
+                tail_length: 10.0,
+                tail_width: 1.5,
+            });
+        }
+        state.remove_lipid(1);
+        state.tags.insert(3, "probe".to_string());
+        state
+    }
+
+    fn frames() -> Vec<(State, (Point, Point))> {
+        vec![
+            (state(0), STRETCHED),
+            (state(100), STRETCHED),
+            (state(200), (BOUNDS.0, Point::new(501.0, 397.0))),
+        ]
+    }
+
+    /// A finished file with `frames`, and where the index starts
+    fn write(header: Header, frames: &[(State, (Point, Point))]) -> (Vec<u8>, u64) {
+        let mut bytes = vec![];
+        let mut w = Writer::new(header, &mut bytes).unwrap();
+        for (state, bounds) in frames {
+            w.write_frame(state, *bounds).unwrap();
+        }
+        let index = w.position;
+        w.finish().unwrap();
+        drop(w);
+        (bytes, index)
+    }
+
+    /// Just the frame block `Writer` writes for `state`
+    fn frame_block(header: Header, state: &State, bounds: (Point, Point)) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut w = Writer::new(header, &mut bytes).unwrap();
+        let start = w.position as usize;
+        w.write_frame(state, bounds).unwrap();
+        let end = w.position as usize;
+        drop(w);
+        bytes[start..end].to_vec()
+    }
+
+    fn assert_same_frame(read: &(State, (Point, Point)), written: &(State, (Point, Point))) {
+        let ((read, read_bounds), (written, written_bounds)) = (read, written);
+        assert_eq!(read_bounds, written_bounds);
+        assert_eq!(
+            (read.tick, read.time, read.time_step),
+            (written.tick, written.time, written.time_step)
+        );
+        assert_eq!(read.next_id, written.next_id);
+        assert_eq!(read.tags, written.tags);
+        assert_eq!(read.lipids.len(), written.lipids.len());
+    }
+
+    #[test]
+    fn exact_frames_round_trip() {
+        let frames = frames();
+        let (bytes, _) = write(header(false), &frames);
+        let mut r = Reader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.header(), &header(false));
+        assert_eq!(r.len(), frames.len());
+        for (iframe, written) in frames.iter().enumerate() {
+            let read = r.read_frame(iframe).unwrap();
+            assert_same_frame(&read, written);
+            for (a, b) in read.0.lipids.iter().zip(written.0.lipids.iter()) {
+                assert_eq!((a.id, a.species), (b.id, b.species));
+                assert_eq!((a.head_position, a.tail_position), (b.head_position, b.tail_position));
+                assert_eq!((a.linear_velocity, a.angular_velocity), (b.linear_velocity, b.angular_velocity));
+                assert_eq!(
+                    (a.head_radius, a.tail_length, a.tail_width),
+                    (b.head_radius, b.tail_length, b.tail_width)
+                );
+            }
+        }
+        assert!(r.read_frame(frames.len()).is_err());
+    }
+
+    #[test]
+    fn quantized_frames_round_trip_within_a_step() {
+        let frames = frames();
+        let (bytes, _) = write(header(true), &frames);
+        let mut r = Reader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.len(), frames.len());
+        for (iframe, written) in frames.iter().enumerate() {
+            let read = r.read_frame(iframe).unwrap();
+            assert_same_frame(&read, written);
+            let bounds = written.1;
+            // half a step of the frame's bounds, and a little for rounding
+            let tolerance = (bounds.1 - bounds.0) / u16::MAX as f32 * 0.5 + Vector::new(1e-4, 1e-4);
+            for (a, b) in read.0.lipids.iter().zip(written.0.lipids.iter()) {
+                assert_eq!((a.id, a.species), (b.id, b.species));
+                for (p, q) in [(a.head_position, b.head_position), (a.tail_position, b.tail_position)] {
+                    assert!(
+                        (p.x - q.x).abs() <= tolerance.x && (p.y - q.y).abs() <= tolerance.y,
+                        "{p:?} vs {q:?}"
+                    );
+                }
+                for (u, v) in [
+                    (a.head_radius, b.head_radius),
+                    (a.tail_length, b.tail_length),
+                    (a.tail_width, b.tail_width),
+                ] {
+                    assert!((u - v).abs() <= 0.5 / SHAPE_SCALE);
+                }
+                assert_eq!((a.linear_velocity, a.angular_velocity), (Vector::new(0.0, 0.0), 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn finished_files_have_an_index() {
+        let (bytes, _) = write(header(false), &frames());
+        let end = bytes.len() as u64;
+        let mut input = Cursor::new(bytes);
+        let index = read_index(&mut input, end).unwrap();
+        assert_eq!(index.as_ref().map(Vec::len), Some(3));
+        let first_block = {
+            input.seek(SeekFrom::Start(0)).unwrap();
+            Header::read(&mut input).unwrap();
+            input.stream_position().unwrap()
+        };
+        assert_eq!(index.unwrap(), scan_frames(&mut input, first_block, end).unwrap());
+    }
+
+    #[test]
+    fn missing_index_is_scanned_for() {
+        let frames = frames();
+        let (mut bytes, index) = write(header(false), &frames);
+        // interrupted before finishing, and partway through the last frame
+        bytes.truncate(index as usize - 3);
+        assert_eq!(read_index(&mut Cursor::new(&bytes), bytes.len() as u64).unwrap(), None);
+        let mut r = Reader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.len(), frames.len() - 1);
+        for (iframe, written) in frames.iter().take(r.len()).enumerate() {
+            assert_same_frame(&r.read_frame(iframe).unwrap(), written);
+        }
+    }
+
+    #[test]
+    fn stale_index_is_scanned_past() {
+        let frames = frames();
+        let (mut bytes, _) = write(header(false), &frames[..2]);
+        bytes.extend(frame_block(header(false), &frames[2].0, frames[2].1));
+        let mut r = Reader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.len(), frames.len());
+        for (iframe, written) in frames.iter().enumerate() {
+            assert_same_frame(&r.read_frame(iframe).unwrap(), written);
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("macrolipid-{name}-{}.mltraj", std::process::id()))
+    }
+
+    /// Writes `frames` to a new file at `path`, and gives where its index starts
+    fn write_file(path: &Path, frames: &[(State, (Point, Point))]) -> u64 {
+        let mut w = Writer::new(header(true), File::create(path).unwrap()).unwrap();
+        for (state, bounds) in frames {
+            w.write_frame(state, *bounds).unwrap();
+        }
+        w.position
+    }
+
+    fn ticks(path: &Path) -> Vec<u64> {
+        let mut r = Reader::open(path).unwrap();
+        (0..r.len()).map(|iframe| r.read_frame(iframe).unwrap().0.tick).collect()
+    }
+
+    #[test]
+    fn appending_carries_on_after_the_frames() {
+        let path = temp_path("append");
+        let frames = frames();
+        write_file(&path, &frames[..2]);
+        {
+            let mut w = Writer::append(&path, frames[1].0.tick).unwrap();
+            w.write_frame(&frames[2].0, frames[2].1).unwrap();
+        }
+        let mut r = Reader::open(&path).unwrap();
+        assert_eq!(r.header(), &header(true));
+        assert_eq!(r.len(), frames.len());
+        for (iframe, written) in frames.iter().enumerate() {
+            assert_same_frame(&r.read_frame(iframe).unwrap(), written);
+        }
+        let end = std::fs::metadata(&path).unwrap().len();
+        assert!(read_index(&mut File::open(&path).unwrap(), end).unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn appending_drops_frames_after_the_resumed_tick() {
+        let path = temp_path("append-resumed");
+        let frames = frames();
+        write_file(&path, &frames);
+        // resumed from a checkpoint at tick 100, so the frame at 200 comes again
+        {
+            let mut w = Writer::append(&path, 100).unwrap();
+            w.write_frame(&frames[2].0, frames[2].1).unwrap();
+            w.write_frame(&state(300), STRETCHED).unwrap();
+        }
+        assert_eq!(ticks(&path), [0, 100, 200, 300]);
+        {
+            // from before the first frame
+            let mut w = Writer::append(&path, 0).unwrap();
+            w.write_frame(&state(100), STRETCHED).unwrap();
+        }
+        assert_eq!(ticks(&path), [0, 100]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn appending_drops_a_cut_off_block() {
+        let path = temp_path("append-cut-off");
+        let frames = frames();
+        let index = write_file(&path, &frames);
+        // interrupted partway through the last frame
+        File::options().write(true).open(&path).unwrap().set_len(index - 3).unwrap();
+        {
+            let mut w = Writer::append(&path, u64::MAX).unwrap();
+            w.write_frame(&frames[2].0, frames[2].1).unwrap();
+        }
+        let mut r = Reader::open(&path).unwrap();
+        assert_eq!(r.len(), frames.len());
+        for (iframe, written) in frames.iter().enumerate() {
+            assert_same_frame(&r.read_frame(iframe).unwrap(), written);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,356 @@
+//! Numbers measured from each `State`, logged as time series.
+
+use crate::analysis;
+use crate::engine::Pull;
+use crate::trajectory::Lines;
+use crate::types::*;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+
+/// Something measured from a state. It may measure several related quantities at once, one per column.
+pub trait Observable {
+    fn columns(&self) -> Vec<String>;
+    /// One value per column, in the same order, for a state in `bounds`.
+    fn measure(&mut self, state: &State, bounds: (Point, Point)) -> Vec<f64>;
+}
+
+/// Names accepted by `by_name`
+pub const NAMES: &[&str] = &[
+    "tick_time",
+    "kinetic_energy",
+    "temperature",
+    "orientation",
+    "water",
+    "pressure",
+    "clusters",
+    "bilayer",
+    "order",
+    "flip_flops",
+];
+
+pub fn by_name(name: &str) -> Option<Box<dyn Observable + Send>> {
+    match name {
+        "tick_time" => Some(Box::new(TickTime)),
+        "kinetic_energy" => Some(Box::new(KineticEnergy)),
+        "temperature" => Some(Box::new(Temperature)),
+        "orientation" => Some(Box::new(MeanOrientation)),
+        "water" => Some(Box::new(WaterStats)),
+        "pressure" => Some(Box::new(Pressure)),
+        "clusters" => Some(Box::new(analysis::clusters::Clusters)),
+        "bilayer" => Some(Box::new(analysis::bilayer::BilayerMetrics::default())),
+        "order" => Some(Box::new(analysis::order::NematicOrder { cell_size: 20.0 })),
+        "flip_flops" => Some(Box::new(analysis::flipflop::FlipFlops::default())),
+        _ => None,
+    }
+}
+
+/// Wall-clock time the engine took for the last tick, in seconds
+pub struct TickTime;
+
+impl Observable for TickTime {
+    fn columns(&self) -> Vec<String> {
+        vec!["tick_time".into()]
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        vec![state.tick_time.as_secs_f64()]
+    }
+}
+
+/// Linear & angular kinetic energy (unit mass & moment of inertia). The forces aren't derived from a potential, so there
+/// is no potential energy to go with it.
+pub struct KineticEnergy;
+
+impl Observable for KineticEnergy {
+    fn columns(&self) -> Vec<String> {
+        vec!["kinetic_linear".into(), "kinetic_angular".into()]
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        let linear = state.lipids.iter().map(|l| 0.5 * l.linear_velocity.magnitude2() as f64).sum();
+        let angular = state.lipids.iter().map(|l| 0.5 * (l.angular_velocity as f64).powi(2)).sum();
+        vec![linear, angular]
+    }
+}
+
+/// Equipartition temperature (k_B = 1) over the two translational and one rotational degrees of freedom of each lipid
+pub struct Temperature;
+
+impl Observable for Temperature {
+    fn columns(&self) -> Vec<String> {
+        vec!["temperature".into()]
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        vec![Temperature::of(state)]
+    }
+}
+
+impl Temperature {
+    /// NaN without lipids
+    pub fn of(state: &State) -> f64 {
+        if state.lipids.is_empty() {
+            return f64::NAN;
+        }
+        let sum: f64 = state
+            .lipids
+            .iter()
+            .map(|l| l.linear_velocity.magnitude2() as f64 + (l.angular_velocity as f64).powi(2))
+            .sum();
+        sum / (3 * state.lipids.len()) as f64
+    }
+}
+
+/// Mean head->tail unit vector, and its length (1 when all lipids point the same way, ~0 when they're random)
+pub struct MeanOrientation;
+
+impl Observable for MeanOrientation {
+    fn columns(&self) -> Vec<String> {
+        vec!["orientation_x".into(), "orientation_y".into(), "polar_order".into()]
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        if state.lipids.is_empty() {
+            return vec![f64::NAN; 3];
+        }
+        let sum = state.lipids.iter().map(Lipid::direction).fold(Vector::new(0.0, 0.0), |a, b| a + b);
+        let mean = sum / state.lipids.len() as f32;
+        vec![mean.x as f64, mean.y as f64, mean.magnitude() as f64]
+    }
+}
+
+/// Summary of `State::water`: mean, standard deviation, and the fractions of cells that are mostly head (> 0.5) or
+/// mostly tail (< -0.5)
+pub struct WaterStats;
+
+impl Observable for WaterStats {
+    fn columns(&self) -> Vec<String> {
+        ["water_mean", "water_std", "water_head_frac", "water_tail_frac"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        let n = state.water.len() as f64;
+        let mean = state.water.iter().map(|w| *w as f64).sum::<f64>() / n;
+        let variance = state.water.iter().map(|w| (*w as f64 - mean).powi(2)).sum::<f64>() / n;
+        let head_frac = state.water.iter().filter(|w| **w > 0.5).count() as f64 / n;
+        let tail_frac = state.water.iter().filter(|w| **w < -0.5).count() as f64 / n;
+        vec![mean, variance.sqrt(), head_frac, tail_frac]
+    }
+}
+
+/// Where the anchor of the pull and the centre of the lipids pulled are, and the force of the spring between them.
+/// Not in `NAMES`, as it needs the pull; NaN once none of the lipids are left.
+pub struct PullForce(pub Pull);
+
+impl Observable for PullForce {
+    fn columns(&self) -> Vec<String> {
+        [
+            "pull_anchor_x",
+            "pull_anchor_y",
+            "pull_centre_x",
+            "pull_centre_y",
+            "pull_force_x",
+            "pull_force_y",
+            "pull_force",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        let Some(r) = self.0.reading(state) else {
+            return vec![f64::NAN; 7];
+        };
+        [
+            r.anchor.x,
+            r.anchor.y,
+            r.centre.x,
+            r.centre.y,
+            r.force.x,
+            r.force.y,
+            r.force.magnitude(),
+        ]
+        .into_iter()
+        .map(f64::from)
+        .collect()
+    }
+}
+
+/// `State::pressure` along x and y, and their mean
+pub struct Pressure;
+
+impl Observable for Pressure {
+    fn columns(&self) -> Vec<String> {
+        vec!["pressure_x".into(), "pressure_y".into(), "pressure".into()]
+    }
+
+    fn measure(&mut self, state: &State, _bounds: (Point, Point)) -> Vec<f64> {
+        let p = state.pressure;
+        vec![p.x as f64, p.y as f64, (p.x + p.y) as f64 / 2.0]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    Csv,
+    JsonLines,
+}
+
+impl LogFormat {
+    /// Guesses from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(LogFormat::Csv),
+            "jsonl" | "ndjson" => Some(LogFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Cuts a log written by `ObservableLog` back to its rows up to `tick` (and the CSV header), for a run resumed there to
+/// append to: later rows are written again by the resumed run, and a row an interrupted run didn't finish would be in the
+/// way.
+pub fn cut_after(path: &Path, format: LogFormat, tick: u64) -> io::Result<()> {
+    let mut lines = Lines::new(BufReader::new(File::open(path)?));
+    let mut keep = 0;
+    let mut header = format == LogFormat::Csv;
+    loop {
+        let line = match lines.next() {
+            Ok(line) => line,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        if header {
+            header = false;
+            keep = lines.end;
+            continue;
+        }
+        let row_tick = match format {
+            LogFormat::Csv => line.split(',').next(),
+            LogFormat::JsonLines => line.strip_prefix("{\"tick\":").and_then(|rest| rest.split([',', '}']).next()),
+        };
+        let Some(row_tick) = row_tick.and_then(|t| t.parse::<f64>().ok()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not a row macrolipid wrote: {line:?}"),
+            ));
+        };
+        if row_tick > tick as f64 {
+            break;
+        }
+        keep = lines.end;
+    }
+    OpenOptions::new().write(true).open(path)?.set_len(keep)
+}
+
+/// Writes one row per call to `log`: the tick and time, then every observable's columns.
+pub struct ObservableLog<W: Write> {
+    format: LogFormat,
+    out: W,
+    observables: Vec<Box<dyn Observable + Send>>,
+    /// Only CSV has one, and it's skipped when appending to an existing log
+    needs_header: bool,
+}
+
+impl<W: Write> ObservableLog<W> {
+    pub fn new(format: LogFormat, out: W, observables: Vec<Box<dyn Observable + Send>>, appending: bool) -> Self {
+        Self {
+            format,
+            out,
+            observables,
+            needs_header: format == LogFormat::Csv && !appending,
+        }
+    }
+
+    fn columns(&self) -> Vec<String> {
+        let mut columns = vec!["tick".to_string(), "time".to_string()];
+        columns.extend(self.observables.iter().flat_map(|o| o.columns()));
+        columns
+    }
+
+    pub fn log(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        let columns = self.columns();
+        let mut values = vec![state.tick as f64, state.time];
+        for o in self.observables.iter_mut() {
+            values.extend(o.measure(state, bounds));
+        }
+
+        match self.format {
+            LogFormat::Csv => {
+                if self.needs_header {
+                    writeln!(self.out, "{}", columns.join(","))?;
+                    self.needs_header = false;
+                }
+                let row: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+                writeln!(self.out, "{}", row.join(","))?;
+            }
+            LogFormat::JsonLines => {
+                // JSON has no NaN or infinities
+                let fields: Vec<_> = columns
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(c, v)| {
+                        if v.is_finite() {
+                            format!("\"{c}\":{v}")
+                        } else {
+                            format!("\"{c}\":null")
+                        }
+                    })
+                    .collect();
+                writeln!(self.out, "{{{}}}", fields.join(","))?;
+            }
+        }
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: (Point, Point) = (Point { x: 3.0, y: 3.0 }, Point { x: 397.0, y: 397.0 });
+
+    /// What `ObservableLog` writes for rows at these ticks
+    fn rows(format: LogFormat, ticks: &[u64]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut log = ObservableLog::new(format, &mut bytes, vec![Box::new(Temperature), Box::new(MeanOrientation)], false);
+        for tick in ticks {
+            let mut state = State::new();
+            state.tick = *tick;
+            state.time = *tick as f64 * 1e-4;
+            log.log(&state, BOUNDS).unwrap();
+        }
+        drop(log);
+        bytes
+    }
+
+    #[test]
+    fn cutting_keeps_the_rows_up_to_the_tick() {
+        for format in [LogFormat::Csv, LogFormat::JsonLines] {
+            let path = std::env::temp_dir().join(format!("macrolipid-cut-{format:?}-{}", std::process::id()));
+            let all = rows(format, &[100, 200, 300]);
+            std::fs::write(&path, &all).unwrap();
+            cut_after(&path, format, 200).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), rows(format, &[100, 200]), "{format:?}");
+
+            // interrupted partway through the last row
+            std::fs::write(&path, &all[..all.len() - 2]).unwrap();
+            cut_after(&path, format, u64::MAX).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), rows(format, &[100, 200]), "{format:?}");
+
+            // the CSV header stays
+            cut_after(&path, format, 0).unwrap();
+            let header = match format {
+                LogFormat::Csv => "tick,time,temperature,orientation_x,orientation_y,polar_order\n",
+                LogFormat::JsonLines => "",
+            };
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), header, "{format:?}");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use piston::Key;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use macrolipid::geometry::{Obstacle, Shape};
+use macrolipid::mltraj;
+use macrolipid::types::*;
+
+/// Plays back a recorded `.mltraj` instead of running the engine.
+pub struct Replay {
+    reader: mltraj::Reader<BufReader<File>>,
+    /// Fractional frame index, so slow speeds still advance
+    position: f64,
+    shown: Option<usize>,
+    playing: bool,
+    /// Frames per second
+    speed: f64,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let reader = mltraj::Reader::open(path)?;
+        if reader.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the trajectory has no frames"));
+        }
+        Ok(Self {
+            reader,
+            position: 0.0,
+            shown: None,
+            playing: true,
+            speed: 30.0,
+        })
+    }
+
+    /// The container & obstacles the run had
+    pub fn walls(&self) -> (Option<Shape>, Vec<Obstacle>) {
+        let header = self.reader.header();
+        (header.container.clone(), header.obstacles.clone())
+    }
+
+    fn last_frame(&self) -> f64 {
+        (self.reader.len() - 1) as f64
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.position = position.clamp(0.0, self.last_frame());
+    }
+
+    pub fn update(&mut self, dt: f64) {
+        if self.playing {
+            self.seek(self.position + self.speed * dt);
+            if self.position >= self.last_frame() {
+                self.playing = false;
+            }
+        }
+    }
+
+    /// Space plays/pauses, Left/Right step a frame, PageUp/PageDown jump a tenth of the way, Home/End go to the
+    /// start/end, and Up/Down double/halve the speed. Returns whether the key was one of those.
+    pub fn key_pressed(&mut self, key: Key) -> bool {
+        let tenth = (self.reader.len() as f64 / 10.0).max(1.0);
+        match key {
+            Key::Space => {
+                if !self.playing && self.position >= self.last_frame() {
+                    self.position = 0.0;
+                }
+                self.playing = !self.playing;
+            }
+            Key::Right => {
+                self.playing = false;
+                self.seek(self.position.floor() + 1.0);
+            }
+            Key::Left => {
+                self.playing = false;
+                self.seek(self.position.ceil() - 1.0);
+            }
+            Key::PageDown => self.seek(self.position + tenth),
+            Key::PageUp => self.seek(self.position - tenth),
+            Key::Home => self.seek(0.0),
+            Key::End => self.seek(self.last_frame()),
+            Key::Up => self.speed = (self.speed * 2.0).min(10000.0),
+            Key::Down => self.speed = (self.speed / 2.0).max(0.1),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The frame to display, with its bounds, if it isn't the one already shown.
+    pub fn new_frame(&mut self) -> io::Result<Option<(State, (Point, Point))>> {
+        let frame = self.position as usize;
+        if self.shown == Some(frame) {
+            return Ok(None);
+        }
+        self.shown = Some(frame);
+        self.reader.read_frame(frame).map(Some)
+    }
+
+    pub fn status(&self) -> String {
+        format!(
+            "Frame {}/{} at {} fps{}",
+            self.position as usize + 1,
+            self.reader.len(),
+            self.speed,
+            if self.playing { "" } else { " (paused)" }
+        )
+    }
+}
@@ -0,0 +1,336 @@
+//! Scenario files: how to set up a run, as `key = value` lines. Blank lines and everything after a `#` are ignored.
+//!
+//! `init` names the initial configuration (see `initialization::generate`), and every other key is a parameter for it:
+//! ```text
+//! # a vesicle of long lipids
+//! init = vesicle
+//! radius = 80
+//! tail_length = 12
+//! ```
+//!
+//! `container` and keys starting with `obstacle.` aren't parameters either: they're a circle or polygon to keep the
+//! lipids in, and obstacles, each with its own name (see `geometry` for the shapes):
+//! ```text
+//! container = circle 200 200 190
+//! obstacle.support = segment 20 250 380 250 hydrophilic
+//! obstacle.pore = circle 200 120 25 hydrophobic
+//! ```
+//!
+//! Nor are the external fields (see `engine::Fields`); vectors are given as x and y, and charges in order of species:
+//! ```text
+//! gravity = 0 200
+//! electric_field = 500 0
+//! charges = -1 0 1
+//! shear = 20
+//! ```
+//!
+//! Keys starting with `pull.` set up steered dynamics (see `engine::Pull`), picking lipids by id or by region:
+//! ```text
+//! pull.region = circle 200 200 15
+//! pull.stiffness = 5000
+//! pull.velocity = 0 -100
+//! ```
+//!
+//! Lipids can be frozen, or held near where they start by springs (see `types::Restraint`), picked the same ways:
+//! ```text
+//! freeze.region = polygon 0 200 400 200 400 400 0 400
+//! restrain.lipids = 0 1 2 3
+//! restrain.stiffness = 200
+//! ```
+//!
+//! Keys starting with `tag.` give the lipids picked, by ids or a region, the rest of the key as their tag (see
+//! `State::tags`); where they overlap, the last tag in alphabetical order wins:
+//! ```text
+//! tag.raft = circle 120 200 30
+//! tag.probe = 7 12
+//! ```
+
+use crate::engine::{Fields, Pull};
+use crate::geometry::{Obstacle, Shape};
+use crate::initialization::Params;
+use crate::types::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Key naming the generator
+pub const INIT_KEY: &str = "init";
+/// Key giving the container
+pub const CONTAINER_KEY: &str = "container";
+/// What keys naming obstacles start with
+pub const OBSTACLE_PREFIX: &str = "obstacle.";
+/// What keys setting up the pull start with
+pub const PULL_PREFIX: &str = "pull.";
+/// What keys picking lipids to freeze start with
+pub const FREEZE_PREFIX: &str = "freeze.";
+/// What keys picking lipids to restrain start with
+pub const RESTRAIN_PREFIX: &str = "restrain.";
+/// What keys tagging lipids start with; the rest of the key is the tag
+pub const TAG_PREFIX: &str = "tag.";
+
+pub fn read(path: &Path) -> io::Result<Params> {
+    parse(&fs::read_to_string(path)?)
+}
+
+pub fn parse(text: &str) -> io::Result<Params> {
+    let mut params = Params::new();
+    for (iline, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: expected key = value", iline + 1),
+            ));
+        };
+        params.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Ok(params)
+}
+
+/// Takes the fields out of `params`; those not given are off. The pull is left for `take_pull`.
+pub fn take_fields(params: &mut Params) -> io::Result<Fields> {
+    let mut fields = Fields::default();
+    if let Some(xy) = take_numbers(params, "gravity", Some(2))? {
+        fields.gravity = Vector::new(xy[0], xy[1]);
+    }
+    if let Some(xy) = take_numbers(params, "electric_field", Some(2))? {
+        fields.electric_field = Vector::new(xy[0], xy[1]);
+    }
+    if let Some(charges) = take_numbers(params, "charges", None)? {
+        fields.charges = charges;
+    }
+    if let Some(shear) = take_numbers(params, "shear", Some(1))? {
+        fields.shear = shear[0];
+    }
+    Ok(fields)
+}
+
+/// Which lipids a pull or restraint picks
+#[derive(Debug, Clone)]
+pub enum Selection {
+    Ids(Vec<LipidId>),
+    /// Those whose midpoint is inside
+    Region(Shape),
+}
+
+impl Selection {
+    /// Ids of the lipids of `state` picked
+    pub fn select(&self, state: &State) -> Vec<LipidId> {
+        match self {
+            Selection::Ids(ids) => ids.iter().copied().filter(|id| state.lipid(*id).is_some()).collect(),
+            Selection::Region(region) => state
+                .lipids
+                .iter()
+                .filter(|l| region.contains(l.midpoint()))
+                .map(|l| l.id)
+                .collect(),
+        }
+    }
+}
+
+/// A pull as the scenario gives it, before there are lipids to pick from
+#[derive(Debug, Clone)]
+pub struct PullSpec {
+    pub selection: Selection,
+    /// Where the anchor starts; the centre of the lipids picked if not given, so the spring starts slack.
+    pub anchor: Option<Point>,
+    pub velocity: Vector,
+    pub stiffness: f32,
+}
+
+impl PullSpec {
+    /// Picks the lipids out of `state`, or fails if none are picked.
+    pub fn resolve(&self, state: &State) -> io::Result<Pull> {
+        let mut pull = Pull {
+            lipids: self.selection.select(state),
+            anchor: self.anchor.unwrap_or(Point::new(0.0, 0.0)),
+            velocity: self.velocity,
+            stiffness: self.stiffness,
+        };
+        let Some(reading) = pull.reading(state) else {
+            return Err(invalid_data("no lipids to pull".to_string()));
+        };
+        if self.anchor.is_none() {
+            pull.anchor = reading.centre;
+        }
+        Ok(pull)
+    }
+}
+
+/// Takes the pull, if any, out of `params`. `pull.stiffness` and either `pull.lipids` (ids) or `pull.region` (a circle
+/// or polygon) are needed; `pull.velocity` and `pull.anchor` are optional.
+pub fn take_pull(params: &mut Params) -> io::Result<Option<PullSpec>> {
+    let selection = take_selection(params, PULL_PREFIX)?;
+    let stiffness = take_numbers(params, "pull.stiffness", Some(1))?;
+    let velocity = take_numbers(params, "pull.velocity", Some(2))?;
+    let anchor = take_numbers(params, "pull.anchor", Some(2))?;
+    reject_unknown(params, PULL_PREFIX, "lipids, region, stiffness, velocity or anchor")?;
+    let (selection, stiffness) = match (selection, stiffness) {
+        (None, None) if velocity.is_none() && anchor.is_none() => return Ok(None),
+        (Some(selection), Some(stiffness)) => (selection, stiffness[0]),
+        _ => {
+            return Err(invalid_data(
+                "a pull needs pull.stiffness, and either pull.lipids or pull.region".to_string(),
+            ));
+        }
+    };
+    Ok(Some(PullSpec {
+        selection,
+        anchor: anchor.map(|xy| Point::new(xy[0], xy[1])),
+        velocity: velocity.map_or(Vector::new(0.0, 0.0), |xy| Vector::new(xy[0], xy[1])),
+        stiffness,
+    }))
+}
+
+/// Lipids to freeze and to restrain, as the scenario gives them
+#[derive(Debug, Clone, Default)]
+pub struct RestraintSpec {
+    pub frozen: Option<Selection>,
+    /// And the stiffness of the springs
+    pub restrained: Option<(Selection, f32)>,
+}
+
+impl RestraintSpec {
+    /// Restraints for the lipids of `state` picked, with harmonic ones pulling back to where they are now. Lipids picked
+    /// for both are frozen.
+    pub fn resolve(&self, state: &State) -> BTreeMap<LipidId, Restraint> {
+        let mut restraints = BTreeMap::new();
+        if let Some((selection, stiffness)) = &self.restrained {
+            for id in selection.select(state) {
+                let lipid = state.lipid(id).expect("selected lipids are in the state");
+                restraints.insert(id, Restraint::harmonic(lipid, *stiffness));
+            }
+        }
+        if let Some(selection) = &self.frozen {
+            restraints.extend(selection.select(state).into_iter().map(|id| (id, Restraint::Frozen)));
+        }
+        restraints
+    }
+}
+
+/// Takes the lipids to freeze (`freeze.lipids` or `freeze.region`) and to restrain (`restrain.lipids` or
+/// `restrain.region`, and `restrain.stiffness`) out of `params`.
+pub fn take_restraints(params: &mut Params) -> io::Result<RestraintSpec> {
+    let frozen = take_selection(params, FREEZE_PREFIX)?;
+    reject_unknown(params, FREEZE_PREFIX, "lipids or region")?;
+    let restrained = take_selection(params, RESTRAIN_PREFIX)?;
+    let stiffness = take_numbers(params, "restrain.stiffness", Some(1))?;
+    reject_unknown(params, RESTRAIN_PREFIX, "lipids, region or stiffness")?;
+    let restrained = match (restrained, stiffness) {
+        (None, None) => None,
+        (Some(selection), Some(stiffness)) => Some((selection, stiffness[0])),
+        _ => {
+            return Err(invalid_data(
+                "restraints need restrain.stiffness, and either restrain.lipids or restrain.region".to_string(),
+            ));
+        }
+    };
+    Ok(RestraintSpec { frozen, restrained })
+}
+
+/// Takes the tags out of `params`, each with the lipids it goes to.
+pub fn take_tags(params: &mut Params) -> io::Result<Vec<(String, Selection)>> {
+    let keys: Vec<String> = params.keys().filter(|key| key.starts_with(TAG_PREFIX)).cloned().collect();
+    keys.into_iter()
+        .map(|key| {
+            let value = params.remove(&key).unwrap_or_default();
+            let tag = &key[TAG_PREFIX.len()..];
+            if tag.is_empty() || tag.contains(char::is_whitespace) {
+                return Err(invalid_data(format!("{key}: tags have to be one word")));
+            }
+            let selection = if value.trim_start().starts_with(|c: char| c.is_ascii_digit()) {
+                parse_ids(&key, &value)?
+            } else {
+                parse_region(&key, &value)?
+            };
+            Ok((tag.to_string(), selection))
+        })
+        .collect()
+}
+
+/// Removes `<prefix>lipids` (ids) or `<prefix>region` (a circle or polygon), whichever is there
+fn take_selection(params: &mut Params, prefix: &str) -> io::Result<Option<Selection>> {
+    let ids_key = format!("{prefix}lipids");
+    let region_key = format!("{prefix}region");
+    match (params.remove(&ids_key), params.remove(&region_key)) {
+        (None, None) => Ok(None),
+        (Some(ids), None) => parse_ids(&ids_key, &ids).map(Some),
+        (None, Some(region)) => parse_region(&region_key, &region).map(Some),
+        (Some(_), Some(_)) => Err(invalid_data(format!("either {ids_key} or {region_key}, not both"))),
+    }
+}
+
+fn parse_ids(key: &str, value: &str) -> io::Result<Selection> {
+    value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map(Selection::Ids)
+        .map_err(|_| invalid_data(format!("{key}: expected lipid ids, not {value:?}")))
+}
+
+fn parse_region(key: &str, value: &str) -> io::Result<Selection> {
+    match value.parse::<Shape>() {
+        Ok(Shape::Segment(..)) => Err(invalid_data(format!("{key}: has to be a circle or polygon"))),
+        Ok(shape) => Ok(Selection::Region(shape)),
+        Err(err) => Err(invalid_data(format!("{key}: {err}"))),
+    }
+}
+
+/// Fails if any key starting with `prefix` is left
+fn reject_unknown(params: &Params, prefix: &str, known: &str) -> io::Result<()> {
+    match params.keys().find(|key| key.starts_with(prefix)) {
+        Some(key) => Err(invalid_data(format!("unknown key {key}, try {prefix}{known}"))),
+        None => Ok(()),
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Removes `key` and parses its value as `count` numbers (or any number of them)
+fn take_numbers(params: &mut Params, key: &str, count: Option<usize>) -> io::Result<Option<Vec<f32>>> {
+    let Some(value) = params.remove(key) else {
+        return Ok(None);
+    };
+    let numbers: Option<Vec<f32>> = value
+        .split_whitespace()
+        .map(|n| n.parse().ok().filter(|n: &f32| n.is_finite()))
+        .collect();
+    match numbers {
+        Some(numbers) if count.is_none_or(|count| numbers.len() == count) => Ok(Some(numbers)),
+        _ => Err(invalid_data(format!(
+            "{key}: expected {} numbers, not {value:?}",
+            count.map_or("some".to_string(), |c| c.to_string())
+        ))),
+    }
+}
+
+/// Takes the container, if any, out of `params`.
+pub fn take_container(params: &mut Params) -> io::Result<Option<Shape>> {
+    let invalid_data = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{CONTAINER_KEY}: {message}"));
+    match params.remove(CONTAINER_KEY).map(|value| value.parse::<Shape>()) {
+        None => Ok(None),
+        Some(Ok(Shape::Segment(..))) => Err(invalid_data("has to be a circle or polygon".to_string())),
+        Some(Ok(shape)) => Ok(Some(shape)),
+        Some(Err(err)) => Err(invalid_data(err)),
+    }
+}
+
+/// Takes the obstacles out of `params`, leaving the generator's parameters.
+pub fn take_obstacles(params: &mut Params) -> io::Result<Vec<Obstacle>> {
+    let keys: Vec<String> = params.keys().filter(|key| key.starts_with(OBSTACLE_PREFIX)).cloned().collect();
+    keys.into_iter()
+        .map(|key| {
+            let value = params.remove(&key).unwrap_or_default();
+            value
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{key}: {err}")))
+        })
+        .collect()
+}
@@ -0,0 +1,300 @@
+//! Trajectory output for the usual analysis tools (VMD, OVITO, MDAnalysis, ...).
+//!
+//! Every lipid is written as one molecule of `1 + TAIL_POINTS.len()` atoms: its head, then the points along the tail
+//! that the engine uses for interactions, the last of which is the tail itself. Molecule numbers are lipid ids + 1, so they
+//! follow lipids from frame to frame. Coordinates are the engine's, unshifted, with z = 0. Extended XYZ also has each
+//! lipid's tag, or `-` for none.
+
+use crate::engine::TAIL_POINTS;
+use crate::types::*;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Extended XYZ, with the box as `Lattice` and `Origin`
+    Xyz,
+    /// GROMACS .gro. The format assumes the box starts at 0, so the box written is the upper corner of the bounds.
+    Gro,
+    /// LAMMPS text dump (`ITEM: ATOMS id mol type x y z`)
+    LammpsDump,
+}
+
+impl Format {
+    /// Guesses from the file extension. (`.mltraj` isn't one of these, see `mltraj`.)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "xyz" | "extxyz" => Some(Format::Xyz),
+            "gro" => Some(Format::Gro),
+            "lammpstrj" | "dump" => Some(Format::LammpsDump),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum AtomKind {
+    Head,
+    TailPoint,
+    Tail,
+}
+
+impl AtomKind {
+    fn name(self) -> &'static str {
+        match self {
+            AtomKind::Head => "H",
+            AtomKind::TailPoint => "C",
+            AtomKind::Tail => "T",
+        }
+    }
+
+    /// LAMMPS wants numeric types
+    fn number(self) -> u32 {
+        match self {
+            AtomKind::Head => 1,
+            AtomKind::TailPoint => 2,
+            AtomKind::Tail => 3,
+        }
+    }
+}
+
+fn atoms(l: &Lipid) -> impl Iterator<Item = (AtomKind, Point)> + '_ {
+    let tail_atoms = TAIL_POINTS.iter().map(move |tail_distance| {
+        let kind = if *tail_distance >= 1.0 {
+            AtomKind::Tail
+        } else {
+            AtomKind::TailPoint
+        };
+        (kind, l.head_position + (l.tail_position - l.head_position) * *tail_distance)
+    });
+    std::iter::once((AtomKind::Head, l.head_position)).chain(tail_atoms)
+}
+
+/// Anything frames can be written to as the simulation goes.
+pub trait TrajectoryOutput {
+    fn write_frame(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()>;
+}
+
+pub struct TrajectoryWriter<W: Write> {
+    format: Format,
+    out: W,
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    pub fn new(format: Format, out: W) -> Self {
+        Self { format, out }
+    }
+
+    fn write_xyz(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        let size = bounds.1 - bounds.0;
+        let w = &mut self.out;
+        writeln!(w, "{}", state.lipids.len() * (1 + TAIL_POINTS.len()))?;
+        writeln!(
+            w,
+            "Lattice=\"{} 0 0 0 {} 0 0 0 1\" Origin=\"{} {} 0\" pbc=\"F F F\" Properties=species:S:1:pos:R:3:molecule_id:I:1:lipid_species:I:1:tag:S:1 Time={} Step={}",
+            size.x, size.y, bounds.0.x, bounds.0.y, state.time, state.tick
+        )?;
+        for l in state.lipids.iter() {
+            let tag = state.tag(l.id).unwrap_or("-");
+            for (kind, p) in atoms(l) {
+                writeln!(w, "{} {} {} 0 {} {} {tag}", kind.name(), p.x, p.y, l.id as u64 + 1, l.species)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fixed columns; residue & atom numbers wrap around at 100000, as usual for .gro. The residue name is `LIP`, with the
+    /// species appended if it isn't 0.
+    fn write_gro(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        let w = &mut self.out;
+        writeln!(w, "macrolipid t= {} step= {}", state.time, state.tick)?;
+        writeln!(w, "{:5}", state.lipids.len() * (1 + TAIL_POINTS.len()))?;
+        let mut iatom = 0;
+        for l in state.lipids.iter() {
+            let residue = if l.species == 0 {
+                "LIP".to_string()
+            } else {
+                format!("LIP{}", l.species % 100)
+            };
+            for (kind, p) in atoms(l) {
+                iatom += 1;
+                writeln!(
+                    w,
+                    "{:>5}{:<5}{:>5}{:>5}{:8.3}{:8.3}{:8.3}",
+                    (l.id as u64 + 1) % 100000,
+                    residue,
+                    kind.name(),
+                    iatom % 100000,
+                    p.x,
+                    p.y,
+                    0.0
+                )?;
+            }
+        }
+        writeln!(w, "{:10.5}{:10.5}{:10.5}", bounds.1.x, bounds.1.y, 1.0)
+    }
+
+    fn write_lammps_dump(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        let w = &mut self.out;
+        writeln!(w, "ITEM: TIMESTEP\n{}", state.tick)?;
+        writeln!(w, "ITEM: NUMBER OF ATOMS\n{}", state.lipids.len() * (1 + TAIL_POINTS.len()))?;
+        writeln!(w, "ITEM: BOX BOUNDS ff ff pp")?;
+        writeln!(w, "{} {}\n{} {}\n-0.5 0.5", bounds.0.x, bounds.1.x, bounds.0.y, bounds.1.y)?;
+        writeln!(w, "ITEM: ATOMS id mol type x y z")?;
+        let mut iatom = 0;
+        for l in state.lipids.iter() {
+            for (kind, p) in atoms(l) {
+                iatom += 1;
+                writeln!(w, "{} {} {} {} {} 0", iatom, l.id as u64 + 1, kind.number(), p.x, p.y)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> TrajectoryOutput for TrajectoryWriter<W> {
+    fn write_frame(&mut self, state: &State, bounds: (Point, Point)) -> io::Result<()> {
+        match self.format {
+            Format::Xyz => self.write_xyz(state, bounds)?,
+            Format::Gro => self.write_gro(state, bounds)?,
+            Format::LammpsDump => self.write_lammps_dump(state, bounds)?,
+        }
+        self.out.flush()
+    }
+}
+
+/// Cuts a file written by `TrajectoryWriter` back to its frames up to `tick`, for a run resumed there to append to: later
+/// frames are written again by the resumed run, and a frame an interrupted run didn't finish would be in the way.
+pub fn cut_after(path: &Path, format: Format, tick: u64) -> io::Result<()> {
+    let mut lines = Lines::new(BufReader::new(File::open(path)?));
+    let mut keep = 0;
+    loop {
+        match skip_frame(format, &mut lines) {
+            Ok(frame_tick) if frame_tick <= tick => keep = lines.end,
+            Ok(_) => break,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+    }
+    OpenOptions::new().write(true).open(path)?.set_len(keep)
+}
+
+/// Reads past one frame and gives its tick.
+fn skip_frame(format: Format, lines: &mut Lines<impl BufRead>) -> io::Result<u64> {
+    let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("not a frame macrolipid wrote: {line:?}"));
+    let number = |line: &str| line.trim().parse::<u64>().map_err(|_| invalid(line));
+    // the tick, and how many lines of the frame are left
+    let (tick, rest) = match format {
+        Format::Xyz => {
+            let atoms = number(lines.next()?)?;
+            let comment = lines.next()?;
+            let tick = comment.split_whitespace().find_map(|word| word.strip_prefix("Step="));
+            (tick.map_or_else(|| Err(invalid(comment)), number)?, atoms)
+        }
+        Format::Gro => {
+            let title = lines.next()?;
+            let tick = title.split_whitespace().skip_while(|word| *word != "step=").nth(1);
+            let tick = tick.map_or_else(|| Err(invalid(title)), number)?;
+            (tick, number(lines.next()?)? + 1)
+        }
+        Format::LammpsDump => {
+            lines.next()?;
+            let tick = number(lines.next()?)?;
+            lines.next()?;
+            // box bounds (4 lines) and the atoms' heading come before the atoms
+            (tick, number(lines.next()?)? + 5)
+        }
+    };
+    for _ in 0..rest {
+        lines.next()?;
+    }
+    Ok(tick)
+}
+
+/// Whole lines of a file, keeping track of where they end
+pub(crate) struct Lines<R> {
+    input: R,
+    line: String,
+    /// Where the last line read ends
+    pub end: u64,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            end: 0,
+        }
+    }
+
+    /// Fails with `UnexpectedEof` at the end of the file, and for a last line cut off before its newline.
+    pub fn next(&mut self) -> io::Result<&str> {
+        self.line.clear();
+        let length = self.input.read_line(&mut self.line)?;
+        if !self.line.ends_with('\n') {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.end += length as u64;
+        Ok(self.line.trim_end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: (Point, Point) = (Point { x: 3.0, y: 3.0 }, Point { x: 397.0, y: 397.0 });
+
+    fn state(tick: u64) -> State {
+        let mut state = State::new();
+        state.tick = tick;
+        for i in 0..3 {
+            let head = Point::new(10.0 + 20.0 * i as f32, 50.0);
+            state.add_lipid(Lipid {
+                id: 0,
+                species: 0,
+                head_position: head,
+                tail_position: head + Vector::new(0.0, 10.0),
+                linear_velocity: Vector::new(0.0, 0.0),
+                angular_velocity: 0.0,
+                head_radius: 2.0,
+                tail_length: 10.0,
+                tail_width: 1.5,
+            });
+        }
+        state.tags.insert(1, "probe".to_string());
+        state
+    }
+
+    /// What `TrajectoryWriter` writes for frames at these ticks
+    fn frames(format: Format, ticks: &[u64]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut w = TrajectoryWriter::new(format, &mut bytes);
+        for tick in ticks {
+            w.write_frame(&state(*tick), BOUNDS).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn cutting_keeps_the_frames_up_to_the_tick() {
+        for format in [Format::Xyz, Format::Gro, Format::LammpsDump] {
+            let path = std::env::temp_dir().join(format!("macrolipid-cut-{format:?}-{}", std::process::id()));
+            let all = frames(format, &[0, 100, 200]);
+            std::fs::write(&path, &all).unwrap();
+            cut_after(&path, format, 100).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), frames(format, &[0, 100]), "{format:?}");
+
+            // interrupted partway through the last frame
+            std::fs::write(&path, &all[..all.len() - 4]).unwrap();
+            cut_after(&path, format, u64::MAX).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), frames(format, &[0, 100]), "{format:?}");
+
+            cut_after(&path, format, 0).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), frames(format, &[0]), "{format:?}");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -1,13 +1,22 @@
-pub use cgmath::prelude::MetricSpace;
 pub use cgmath::Point2;
 pub use cgmath::Vector2;
+pub use cgmath::prelude::InnerSpace;
+pub use cgmath::prelude::MetricSpace;
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 pub type Point = Point2<f32>;
 pub type Vector = Vector2<f32>;
 
+/// Identifies a lipid for as long as it exists, whatever happens to the others. Never reused within a run.
+pub type LipidId = u32;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Lipid {
+    /// Given out by `State::add_lipid`
+    pub id: LipidId,
+    /// What kind of lipid this is; 0 unless the setup says otherwise. Only used to tell lipids apart, e.g. by colour.
+    pub species: u16,
     pub head_position: Point2<f32>,
     pub tail_position: Point2<f32>,
     pub linear_velocity: Vector2<f32>,
@@ -17,10 +26,65 @@ pub struct Lipid {
     pub tail_width: f32,
 }
 
+impl Lipid {
+    /// Unit vector pointing from the head to the tail
+    pub fn direction(&self) -> Vector {
+        (self.tail_position - self.head_position).normalize()
+    }
+
+    /// Point `frac` of the way from the head to the tail
+    pub fn tail_point(&self, frac: f32) -> Point {
+        self.head_position + (self.tail_position - self.head_position) * frac
+    }
+
+    pub fn midpoint(&self) -> Point {
+        self.tail_point(0.5)
+    }
+}
+
+/// Holds a lipid in place
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Restraint {
+    /// Doesn't move at all, though others still bump into it
+    Frozen,
+    /// Springs pull its head and tail back towards these, which keeps both its position and its orientation.
+    Harmonic { head: Point, tail: Point, stiffness: f32 },
+}
+
+impl Restraint {
+    /// Springs from where `lipid` is now
+    pub fn harmonic(lipid: &Lipid, stiffness: f32) -> Self {
+        Restraint::Harmonic {
+            head: lipid.head_position,
+            tail: lipid.tail_position,
+            stiffness,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct State {
+    /// In no particular order: refer to a lipid by its `id` rather than its index if that needs to hold across states
     pub lipids: Vec<Lipid>,
+    /// The id the next lipid added will get
+    pub next_id: LipidId,
+    /// Optional user-given labels, by lipid id
+    pub tags: BTreeMap<LipidId, String>,
+    /// Lipids held in place, by lipid id
+    pub restraints: BTreeMap<LipidId, Restraint>,
+    /// Number of ticks the engine has done to get here
+    pub tick: u64,
+    /// Simulated time, i.e. the sum of all the steps taken
+    pub time: f64,
     pub tick_time: Duration,
+    /// The step actually taken by the last tick (may differ from the configured one, see `engine::TimeStep`)
+    pub time_step: f32,
+    /// Pressure along x and y during the last tick: the kinetic part (unit masses) and the virial of the forces between
+    /// lipids, over the area of the bounds. The water isn't counted, as its forces don't come in pairs.
+    pub pressure: Vector2<f32>,
+    /// Water field (clamped to -1..=1, indexed `[y, x]`) the lipids felt during the last tick. It covers the bounds, so its
+    /// size changes with them.
+    pub water: ndarray::Array2<f32>,
     pub debug_array0: ndarray::Array3<u8>,
 }
 
@@ -28,8 +92,50 @@ impl State {
     pub fn new() -> Self {
         Self {
             lipids: vec![],
+            next_id: 0,
+            tags: BTreeMap::new(),
+            restraints: BTreeMap::new(),
+            tick: 0,
+            time: 0.0,
             tick_time: Duration::ZERO,
+            time_step: 0.0,
+            pressure: Vector2::new(0.0, 0.0),
+            water: ndarray::Array2::zeros((400, 400)),
             debug_array0: ndarray::Array3::zeros((400, 400, 4)),
         }
     }
+
+    /// Adds `lipid` with a fresh id (whatever `lipid.id` was), and returns it.
+    pub fn add_lipid(&mut self, mut lipid: Lipid) -> LipidId {
+        lipid.id = self.next_id;
+        self.next_id += 1;
+        self.lipids.push(lipid);
+        lipid.id
+    }
+
+    /// Removes the lipid and its tag & restraint, if it has them. The other lipids may move to different indices.
+    pub fn remove_lipid(&mut self, id: LipidId) -> Option<Lipid> {
+        let index = self.index_of(id)?;
+        self.tags.remove(&id);
+        self.restraints.remove(&id);
+        Some(self.lipids.swap_remove(index))
+    }
+
+    pub fn index_of(&self, id: LipidId) -> Option<usize> {
+        self.lipids.iter().position(|l| l.id == id)
+    }
+
+    pub fn lipid(&self, id: LipidId) -> Option<&Lipid> {
+        self.lipids.iter().find(|l| l.id == id)
+    }
+
+    pub fn tag(&self, id: LipidId) -> Option<&str> {
+        self.tags.get(&id).map(String::as_str)
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }